@@ -0,0 +1,127 @@
+//! Room creation and join throughput with many tasks hitting the registry at once.
+
+use std::{
+    future::Future,
+    net::{TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+use chatroom_rust::{game::GameOptions, server::Server, transport::Connection};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// Tasks running at the same time in every benchmark.
+const TASKS: usize = 64;
+
+/// Runs `iters` rounds of `round`, each returning the time it spent on what is measured.
+///
+/// Rooms stay alive for a minute without players, so every sample gets its own runtime,
+/// whose drop stops the rooms it created.
+fn measure<F>(iters: u64, round: impl Fn() -> F) -> Duration
+where
+    F: Future<Output = Duration>,
+{
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Should build the runtime");
+    runtime.block_on(async {
+        let mut elapsed = Duration::ZERO;
+        for _ in 0..iters {
+            elapsed += round().await;
+        }
+        elapsed
+    })
+}
+
+async fn create_games(server: &Server, games: usize) -> Vec<String> {
+    let tasks = (0..TASKS)
+        .map(|task| {
+            let server = server.clone();
+            tokio::spawn(async move {
+                let mut codes = Vec::new();
+                for _ in (task..games).step_by(TASKS) {
+                    codes.push(server.new_game(GameOptions::default()).await.unwrap());
+                }
+                codes
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut codes = Vec::new();
+    for task in tasks {
+        codes.extend(task.await.unwrap());
+    }
+    codes
+}
+
+/// Connected socket pairs; the client ends are kept open until the batch is dropped.
+fn socket_pairs(count: usize) -> Vec<(TcpStream, TcpStream)> {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    (0..count)
+        .map(|_| {
+            let client = TcpStream::connect(address).unwrap();
+            let (server_end, _) = listener.accept().unwrap();
+            server_end.set_nonblocking(true).unwrap();
+            (server_end, client)
+        })
+        .collect()
+}
+
+fn create(c: &mut Criterion) {
+    let mut group = c.benchmark_group("create");
+    for games in [100, 1000] {
+        group.bench_with_input(BenchmarkId::from_parameter(games), &games, |b, &games| {
+            b.iter_custom(|iters| {
+                measure(iters, || async {
+                    let server = Server::default();
+                    let started = Instant::now();
+                    create_games(&server, games).await;
+                    started.elapsed()
+                })
+            });
+        });
+    }
+    group.finish();
+}
+
+/// Players join existing rooms, some ask for rooms that do not exist and get closed, while
+/// new rooms keep being created.
+fn create_and_join(c: &mut Criterion) {
+    let joins = 256;
+    c.bench_function("create_and_join", |b| {
+        b.iter_custom(|iters| {
+            measure(iters, || async {
+                let server = Server::default();
+                let codes = create_games(&server, 16).await;
+                let sockets = socket_pairs(joins);
+                let started = Instant::now();
+                let mut clients = Vec::new();
+                let mut tasks = Vec::new();
+                for (i, (socket, client)) in sockets.into_iter().enumerate() {
+                    clients.push(client);
+                    let server = server.clone();
+                    let game_code = match i % 8 {
+                        0 => "missing".to_string(),
+                        _ => codes[i % codes.len()].clone(),
+                    };
+                    tasks.push(tokio::spawn(async move {
+                        let socket = tokio::net::TcpStream::from_std(socket).unwrap();
+                        let _ = server
+                            .add_player_to_game(Connection::tcp(socket), &game_code)
+                            .await;
+                    }));
+                }
+                tasks.push(tokio::spawn(async move {
+                    create_games(&server, joins).await;
+                }));
+                for task in tasks {
+                    task.await.unwrap();
+                }
+                started.elapsed()
+            })
+        });
+    });
+}
+
+criterion_group!(benches, create, create_and_join);
+criterion_main!(benches);
@@ -1,4 +1,6 @@
 rustup update
 git fetch --all
 git reset --hard origin/main
+npm ci
+npm run build
 cargo run --release
\ No newline at end of file
@@ -0,0 +1,123 @@
+use std::path::{Path, PathBuf};
+
+use warp::{
+    filters::{fs::File, path::FullPath},
+    http::header::CACHE_CONTROL,
+    reject::Rejection,
+    reply::Reply,
+    Filter,
+};
+
+/// Files with a content hash in their name never change, so browsers may keep them forever.
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+/// Everything else, `index.html` included, is revalidated with `If-Modified-Since`.
+const REVALIDATE: &str = "no-cache";
+
+/// Serves the built frontend from `dir`.
+///
+/// Existing files are served as they are. Any other `GET` whose last path segment has no
+/// extension gets `index.html`, so client-side routes survive a reload; missing assets
+/// still 404.
+pub fn static_files(
+    dir: impl Into<PathBuf>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let dir = dir.into();
+    let index = dir.join("index.html");
+    let files = warp::get().and(warp::fs::dir(dir)).map(with_cache_control);
+    let spa_fallback = warp::get()
+        .and(warp::path::full())
+        .and_then(|path: FullPath| async move {
+            if is_client_route(path.as_str()) {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+        .and(warp::fs::file(index))
+        .map(with_cache_control);
+    files.or(spa_fallback).unify()
+}
+
+fn with_cache_control(file: File) -> impl Reply {
+    let cache_control = cache_control(file.path());
+    warp::reply::with_header(file, CACHE_CONTROL, cache_control)
+}
+
+/// Picks the `Cache-Control` value for a file.
+///
+/// A name counts as hashed when a segment between the base name and the extension, split on
+/// `.` or `-`, is at least 8 alphanumeric characters with a digit, as bundlers emit for
+/// `app.3f9a2c1d.js` or `index-B2x8Kq1z.css`.
+pub fn cache_control(path: &Path) -> &'static str {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return REVALIDATE;
+    };
+    let segments = name.split(['.', '-']).collect::<Vec<_>>();
+    let hashed = segments.len() > 2
+        && segments[1..segments.len() - 1].iter().any(|segment| {
+            segment.len() >= 8
+                && segment.chars().all(|c| c.is_ascii_alphanumeric())
+                && segment.chars().any(|c| c.is_ascii_digit())
+        });
+    if hashed {
+        IMMUTABLE
+    } else {
+        REVALIDATE
+    }
+}
+
+fn is_client_route(path: &str) -> bool {
+    !path.rsplit('/').next().unwrap_or_default().contains('.')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn hashed_assets_are_immutable() {
+        assert_eq!(
+            cache_control(Path::new("assets/app.3f9a2c1d.js")),
+            IMMUTABLE
+        );
+        assert_eq!(cache_control(Path::new("index-B2x8Kq1z.css")), IMMUTABLE);
+        assert_eq!(cache_control(Path::new("index.html")), REVALIDATE);
+        assert_eq!(cache_control(Path::new("main.css")), REVALIDATE);
+        assert_eq!(cache_control(Path::new("game-board.css")), REVALIDATE);
+    }
+
+    #[tokio::test]
+    async fn serves_files_and_falls_back_to_index() {
+        let dir = std::env::temp_dir().join(format!("chatroom-assets-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("index.html"), "<html></html>").unwrap();
+        fs::write(dir.join("main.3f9a2c1d.css"), "body {}").unwrap();
+        let filter = static_files(dir.clone());
+
+        let css = warp::test::request()
+            .path("/main.3f9a2c1d.css")
+            .reply(&filter)
+            .await;
+        assert_eq!(css.status(), 200);
+        assert_eq!(css.headers()[CACHE_CONTROL], IMMUTABLE);
+        assert_eq!(css.headers()["content-type"], "text/css");
+
+        for path in ["/", "/room/abc"] {
+            let index = warp::test::request().path(path).reply(&filter).await;
+            assert_eq!(index.status(), 200, "{path}");
+            assert_eq!(index.body(), "<html></html>");
+            assert_eq!(index.headers()[CACHE_CONTROL], REVALIDATE);
+        }
+
+        let missing = warp::test::request()
+            .path("/missing.js")
+            .reply(&filter)
+            .await;
+        assert_eq!(missing.status(), 404);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+use std::sync::Arc;
+
+use chatroom_rust::{
+    player::{PlayerAction, PlayerMessage, Score},
+    stack::{Card, Stack},
+};
+
+/// How many log lines are kept for the log pane.
+const LOG_LEN: usize = 200;
+
+/// Everything the terminal client knows about the game, updated from server messages.
+pub struct App {
+    pub game_code: String,
+    pub name: Arc<str>,
+    pub players: Vec<Arc<str>>,
+    pub scores: Vec<Score>,
+    pub stack: Stack,
+    pub hand: Vec<Card>,
+    pub playing: Option<Arc<str>>,
+    pub point: Option<i32>,
+    pub host: bool,
+    /// Set once the server sends a hand, the hand then stays between turns.
+    pub deck: bool,
+    pub log: Vec<String>,
+    pub input: String,
+    pub quit: bool,
+}
+
+impl App {
+    pub fn new(game_code: String, name: Arc<str>) -> Self {
+        Self {
+            game_code,
+            name,
+            players: Vec::new(),
+            scores: Vec::new(),
+            stack: Stack::default(),
+            hand: Vec::new(),
+            playing: None,
+            point: None,
+            host: false,
+            deck: false,
+            log: Vec::new(),
+            input: String::new(),
+            quit: false,
+        }
+    }
+
+    pub fn my_turn(&self) -> bool {
+        self.playing.as_ref() == Some(&self.name)
+    }
+
+    pub fn push_log(&mut self, line: impl Into<String>) {
+        self.log.push(line.into());
+        if self.log.len() > LOG_LEN {
+            self.log.remove(0);
+        }
+    }
+
+    pub fn handle_message(&mut self, msg: PlayerMessage) {
+        match msg {
+            PlayerMessage::Welcome {
+                protocol_version, ..
+            } => self.push_log(format!("Connected, protocol version {protocol_version}")),
+            PlayerMessage::Joined { players_name } => {
+                self.players = players_name;
+                self.players.push(self.name.clone());
+                self.push_log(format!("Joined room {}", self.game_code));
+            }
+            PlayerMessage::HostStart => {
+                self.host = true;
+                self.push_log("You are the host, type /start when everyone is here");
+            }
+            PlayerMessage::NewPlayer { name } => {
+                self.push_log(format!("{name} joined"));
+                self.players.push(name);
+            }
+            PlayerMessage::StartFailed => self.push_log("Need at least two players to start"),
+            PlayerMessage::Start { point, seating } => {
+                self.point = Some(point);
+                if seating.is_empty() {
+                    self.push_log("Game started");
+                } else {
+                    self.push_log(format!("Game started, seating: {}", seating.join(", ")));
+                    self.players = seating;
+                }
+            }
+            PlayerMessage::Scoreboard { scores } => self.scores = scores,
+            PlayerMessage::RoundStart {
+                player_name,
+                stack,
+                point,
+            } => {
+                self.stack = stack;
+                self.point = point;
+                if !self.deck {
+                    self.hand.clear();
+                }
+                self.playing = Some(player_name);
+            }
+            PlayerMessage::NewRound { cards, stack } => {
+                self.hand = cards;
+                self.stack = stack;
+                self.push_log("Your turn, type a card number to play it");
+            }
+            PlayerMessage::PreviewResult {
+                stack_after,
+                point_changes,
+                ..
+            } => {
+                let changes = point_changes
+                    .iter()
+                    .filter(|change| change.change != 0)
+                    .map(|change| format!("{} {:+}", change.name, change.change))
+                    .collect::<Vec<_>>();
+                self.push_log(format!(
+                    "Preview: stack {:?}{}",
+                    stack_after.values(),
+                    if changes.is_empty() {
+                        String::new()
+                    } else {
+                        format!(", {}", changes.join(", "))
+                    }
+                ));
+            }
+            PlayerMessage::Hand { cards } => {
+                self.deck = true;
+                self.hand = cards;
+            }
+            PlayerMessage::OtherUseCard { card, target } => {
+                let player = self.playing.clone().unwrap_or_else(|| "?".into());
+                match target {
+                    Some(target) => self.push_log(format!("{player} played [{card}] at {target}")),
+                    None => self.push_log(format!("{player} played [{card}]")),
+                }
+            }
+            PlayerMessage::OtherDiscard { card } => {
+                let player = self.playing.clone().unwrap_or_else(|| "?".into());
+                self.push_log(format!("{player} discarded [{card}]"));
+            }
+            PlayerMessage::OtherPass => {
+                let player = self.playing.clone().unwrap_or_else(|| "?".into());
+                self.push_log(format!("{player} passed"));
+            }
+            PlayerMessage::PowerUsed {
+                player_name,
+                power,
+                target,
+            } => match target {
+                Some(target) => self.push_log(format!("{player_name} used {power} on {target}")),
+                None => self.push_log(format!("{player_name} used {power}")),
+            },
+            PlayerMessage::Peek { player_name, cards } => {
+                let cards = cards
+                    .iter()
+                    .map(|card| format!("[{card}]"))
+                    .collect::<Vec<_>>();
+                self.push_log(format!("{player_name} holds {}", cards.join(" ")));
+            }
+            PlayerMessage::Lose => self.push_log("You lost all your points"),
+            PlayerMessage::Win => self.push_log("You won!"),
+            PlayerMessage::GameEnd {
+                winner_names,
+                winning_teams,
+                ..
+            } => {
+                let winners = winner_names.join(", ");
+                match (winning_teams.as_slice(), winner_names.len()) {
+                    ([], 0) => self.push_log("Game over, nobody won"),
+                    ([], 1) => self.push_log(format!("Game over, {winners} won")),
+                    ([], _) => self.push_log(format!("Game over, tie between {winners}")),
+                    (teams, _) => {
+                        let teams = teams
+                            .iter()
+                            .map(ToString::to_string)
+                            .collect::<Vec<_>>()
+                            .join(" and ");
+                        self.push_log(format!("Game over, team {teams} won: {winners}"));
+                    }
+                }
+            }
+            PlayerMessage::Teams { teams } => {
+                for (team, members) in teams.iter().enumerate() {
+                    self.push_log(format!("Team {team}: {}", members.join(", ")));
+                }
+            }
+            PlayerMessage::GameStarted => self.push_log("This game has already started"),
+            PlayerMessage::GameEnded => self.push_log("The room was closed"),
+            PlayerMessage::Chat { player_name, text } => {
+                self.push_log(format!("<{player_name}> {text}"));
+            }
+            PlayerMessage::Error { message, .. } => self.push_log(format!("Error: {message}")),
+        }
+    }
+
+    /// Turns the input line into an action, or handles it locally.
+    ///
+    /// A number plays that card, `/preview N`, `/discard N`, `/team N`, `/pass`, `/start`
+    /// and `/quit` are commands, anything else is chat. Playing and previewing take an
+    /// optional ` @name` target.
+    pub fn submit(&mut self) -> Option<PlayerAction> {
+        let input = std::mem::take(&mut self.input);
+        let input = input.trim();
+        if input.is_empty() {
+            return None;
+        }
+        let (command, target) = match input.split_once(" @") {
+            Some((command, target)) => (command.trim(), Some(Arc::from(target.trim()))),
+            None => (input, None),
+        };
+        if let Ok(card_index) = command.parse::<usize>() {
+            if card_index >= self.hand.len() {
+                self.push_log(format!("No card {card_index} in your hand"));
+                return None;
+            }
+            // The hand is only updated once the server accepts the play and deals again.
+            self.push_log(format!("Playing [{}]", self.hand[card_index]));
+            return Some(PlayerAction::UseCard { card_index, target });
+        }
+        if let Some(card_index) = command.strip_prefix("/preview ") {
+            return match card_index.trim().parse::<usize>() {
+                Ok(card_index) => Some(PlayerAction::Preview { card_index, target }),
+                Err(_) => {
+                    self.push_log(format!("No card {card_index} in your hand"));
+                    None
+                }
+            };
+        }
+        if let Some(team) = input.strip_prefix("/team ") {
+            return match team.trim().parse::<usize>() {
+                Ok(team) => Some(PlayerAction::PickTeam { team }),
+                Err(_) => {
+                    self.push_log(format!("No team {team}"));
+                    None
+                }
+            };
+        }
+        if let Some(card_index) = input.strip_prefix("/discard ") {
+            let Some(card_index) = card_index
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|card_index| *card_index < self.hand.len())
+            else {
+                self.push_log(format!("No card {card_index} in your hand"));
+                return None;
+            };
+            self.push_log(format!("Discarding [{}]", self.hand[card_index]));
+            return Some(PlayerAction::Discard { card_index });
+        }
+        match input {
+            "/start" => Some(PlayerAction::Start),
+            "/pass" => Some(PlayerAction::Pass),
+            "/quit" => {
+                self.quit = true;
+                Some(PlayerAction::Quit)
+            }
+            _ if input.starts_with('/') => {
+                self.push_log(format!("Unknown command {input}"));
+                None
+            }
+            _ => Some(PlayerAction::Chat { text: input.into() }),
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Terminal client: `chatroom-cli [--server HOST:PORT] [--name NAME] (create | join CODE)`.
+//!
+//! Only built with the `clients` feature.
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use app::App;
+use chatroom_rust::{
+    client,
+    player::{PlayerAction, PlayerMessage},
+    protocol::{Feature, PROTOCOL_VERSION},
+};
+use crossterm::event::{Event, EventStream, KeyCode, KeyEventKind, KeyModifiers};
+use futures::{SinkExt, StreamExt};
+use ratatui::DefaultTerminal;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+mod app;
+mod ui;
+
+enum Command {
+    Create,
+    Join(String),
+}
+
+struct Args {
+    server: String,
+    name: String,
+    command: Command,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut server = "127.0.0.1:8080".to_string();
+        let mut name = std::env::var("USER").unwrap_or_else(|_| "player".to_string());
+        let mut command = None;
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--server" => {
+                    server = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--server needs a value"))?
+                }
+                "--name" => name = args.next().ok_or_else(|| anyhow!("--name needs a value"))?,
+                "create" => command = Some(Command::Create),
+                "join" => {
+                    let game_code = args
+                        .next()
+                        .ok_or_else(|| anyhow!("join needs a game code"))?;
+                    command = Some(Command::Join(game_code));
+                }
+                _ => bail!("Unknown argument {arg}"),
+            }
+        }
+        let command = command.ok_or_else(|| {
+            anyhow!("Usage: chatroom-cli [--server HOST:PORT] [--name NAME] (create | join CODE)")
+        })?;
+        Ok(Self {
+            server,
+            name,
+            command,
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse()?;
+    let game_code = match args.command {
+        Command::Create => client::create_game(&args.server, None).await?,
+        Command::Join(game_code) => {
+            if !client::game_exist(&args.server, &game_code).await? {
+                bail!("Game {game_code} does not exist");
+            }
+            game_code
+        }
+    };
+    let (ws, _) = connect_async(format!("ws://{}/game/{game_code}", args.server)).await?;
+
+    let terminal = ratatui::init();
+    let result = run(terminal, ws, App::new(game_code, args.name.into())).await;
+    ratatui::restore();
+    result
+}
+
+async fn run(
+    mut terminal: DefaultTerminal,
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    mut app: App,
+) -> Result<()> {
+    let (mut ws_sender, mut ws_receiver) = ws.split();
+    let mut events = EventStream::new();
+    let hello = PlayerAction::Hello {
+        protocol_version: PROTOCOL_VERSION,
+        client_name: Arc::from("chatroom-cli"),
+        capabilities: vec![Feature::TypedErrors, Feature::Chat],
+    };
+    let join = PlayerAction::Join {
+        name: app.name.clone(),
+    };
+    for action in [hello, join] {
+        ws_sender
+            .send(Message::Text(serde_json::to_string(&action)?))
+            .await?;
+    }
+
+    while !app.quit {
+        terminal.draw(|frame| ui::draw(frame, &app))?;
+        tokio::select! {
+            Some(msg) = ws_receiver.next() => match msg? {
+                Message::Text(text) => match serde_json::from_str::<PlayerMessage>(&text) {
+                    Ok(msg) => app.handle_message(msg),
+                    Err(err) => app.push_log(format!("Unreadable message: {err}")),
+                },
+                Message::Close(frame) => {
+                    let reason = frame.map(|frame| frame.reason.to_string()).unwrap_or_default();
+                    app.push_log(format!("Disconnected {reason}, press Esc to exit"));
+                }
+                _ => (),
+            },
+            Some(event) = events.next() => {
+                let Event::Key(key) = event? else {
+                    continue;
+                };
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Esc => app.quit = true,
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        app.quit = true;
+                    }
+                    KeyCode::Char(c) => app.input.push(c),
+                    KeyCode::Backspace => {
+                        app.input.pop();
+                    }
+                    KeyCode::Enter => {
+                        if let Some(action) = app.submit() {
+                            ws_sender
+                                .send(Message::Text(serde_json::to_string(&action)?))
+                                .await?;
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+    let _ = ws_sender.close().await;
+    Ok(())
+}
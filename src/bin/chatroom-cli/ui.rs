@@ -0,0 +1,128 @@
+use ratatui::{
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, Paragraph, Wrap},
+    Frame,
+};
+
+use crate::app::App;
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    let [header, body, input] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(8),
+        Constraint::Length(3),
+    ])
+    .areas(frame.area());
+    let [left, right] =
+        Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)]).areas(body);
+    let [stack, hand] = Layout::vertical([Constraint::Length(5), Constraint::Min(3)]).areas(left);
+    let [scoreboard, log] =
+        Layout::vertical([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(right);
+
+    let turn = match &app.playing {
+        Some(_) if app.my_turn() => "your turn".to_string(),
+        Some(player) => format!("{player}'s turn"),
+        None => "waiting for start".to_string(),
+    };
+    let point = app
+        .point
+        .map_or_else(|| "-".to_string(), |point| point.to_string());
+    frame.render_widget(
+        Paragraph::new(format!(
+            "Room {} | {}{} | points {} | {}",
+            app.game_code,
+            app.name,
+            if app.host { " (host)" } else { "" },
+            point,
+            turn
+        ))
+        .block(Block::bordered().title("chatroom")),
+        header,
+    );
+
+    let values = app
+        .stack
+        .values()
+        .iter()
+        .map(|num| Span::styled(format!("{num:>4}"), Style::new().fg(Color::Cyan)))
+        .collect::<Vec<_>>();
+    frame.render_widget(
+        Paragraph::new(vec![
+            Line::from(values),
+            Line::from(format!(
+                "{}/{} (top is rightmost)",
+                app.stack.values().len(),
+                app.stack.len()
+            )),
+        ])
+        .block(Block::bordered().title("Stack")),
+        stack,
+    );
+
+    let cards = app
+        .hand
+        .iter()
+        .enumerate()
+        .map(|(index, card)| ListItem::new(format!("{index}: {card}")))
+        .collect::<Vec<_>>();
+    frame.render_widget(
+        List::new(cards).block(Block::bordered().title("Hand")),
+        hand,
+    );
+
+    let scores = if app.scores.is_empty() {
+        app.players
+            .iter()
+            .map(|name| ListItem::new(name.to_string()))
+            .collect::<Vec<_>>()
+    } else {
+        app.scores
+            .iter()
+            .map(|score| {
+                let style = if app.playing.as_ref() == Some(&score.name) {
+                    Style::new().add_modifier(Modifier::BOLD)
+                } else {
+                    Style::new()
+                };
+                let point = score
+                    .point
+                    .map_or_else(|| "out".to_string(), |point| point.to_string());
+                let hand = score
+                    .hand_size
+                    .map_or_else(String::new, |size| format!(" {size:>2} cards"));
+                let team = score
+                    .team
+                    .map_or_else(String::new, |team| format!(" team {team}"));
+                ListItem::new(format!("{:<16} {point:>4}{hand}{team}", score.name)).style(style)
+            })
+            .collect()
+    };
+    frame.render_widget(
+        List::new(scores).block(Block::bordered().title("Scoreboard")),
+        scoreboard,
+    );
+
+    let visible = log.height.saturating_sub(2) as usize;
+    let lines = app
+        .log
+        .iter()
+        .skip(app.log.len().saturating_sub(visible))
+        .map(|line| Line::from(line.as_str()))
+        .collect::<Vec<_>>();
+    frame.render_widget(
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(Block::bordered().title("Log")),
+        log,
+    );
+
+    frame.render_widget(
+        Paragraph::new(app.input.as_str()).block(Block::bordered().title(
+            "number: play card | /preview N | /discard N | /pass | /start | /quit | text: chat | Esc: exit",
+        )),
+        input,
+    );
+    frame.set_cursor_position((input.x + 1 + app.input.len() as u16, input.y + 1));
+}
@@ -0,0 +1,403 @@
+//! Load generator: `loadtest [--server HOST:PORT] [--rooms N] [--players N]
+//! [--concurrency N] [--room JSON] [--timeout SECS]`.
+//!
+//! Creates rooms with `POST /create-game`, fills each with bots over WebSockets that play a
+//! full game, and prints a JSON report of latencies, connection failures and server errors.
+//! `--concurrency` caps how many rooms play at once, all of them by default.
+//!
+//! Only built with the `clients` feature.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use chatroom_rust::{
+    bot, client,
+    game::GameOptions,
+    player::{PlayerAction, PlayerMessage, Score},
+    protocol::PROTOCOL_VERSION,
+};
+use futures::{stream::SplitSink, SinkExt, StreamExt};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+use tokio::{net::TcpStream, sync::Semaphore, time::timeout};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+struct Args {
+    server: String,
+    rooms: usize,
+    players: usize,
+    concurrency: Option<usize>,
+    /// The `POST /create-game` body, sent as given.
+    room: Option<String>,
+    targeted: bool,
+    timeout: Duration,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut args = Self {
+            server: "127.0.0.1:8080".to_string(),
+            rooms: 100,
+            players: 4,
+            concurrency: None,
+            room: None,
+            targeted: false,
+            timeout: Duration::from_secs(120),
+        };
+        let mut argv = std::env::args().skip(1);
+        while let Some(arg) = argv.next() {
+            let value = argv.next().ok_or_else(|| anyhow!("{arg} needs a value"))?;
+            match arg.as_str() {
+                "--server" => args.server = value,
+                "--rooms" => args.rooms = value.parse().context("Invalid --rooms")?,
+                "--players" => args.players = value.parse().context("Invalid --players")?,
+                "--concurrency" => {
+                    args.concurrency = Some(value.parse().context("Invalid --concurrency")?)
+                }
+                "--room" => {
+                    let options: GameOptions =
+                        serde_json::from_str(&value).context("Invalid --room")?;
+                    args.targeted = options.targeted;
+                    args.room = Some(value);
+                }
+                "--timeout" => {
+                    args.timeout = Duration::from_secs(value.parse().context("Invalid --timeout")?)
+                }
+                _ => bail!("Unknown argument {arg}"),
+            }
+        }
+        if args.players < 2 {
+            bail!("--players needs at least two players");
+        }
+        if args.concurrency == Some(0) {
+            bail!("--concurrency should not be 0");
+        }
+        Ok(args)
+    }
+}
+
+/// What one bot measured during its game.
+#[derive(Default)]
+struct BotStats {
+    /// From sending an action to the first message answering it.
+    round_trips: Vec<Duration>,
+    /// From another bot playing a card to this one hearing of it.
+    broadcasts: Vec<Duration>,
+    errors: BTreeMap<String, usize>,
+    finished: bool,
+    disconnected: bool,
+    timed_out: bool,
+}
+
+/// A scripted player: plays a random card every turn, on the strongest opponent in a
+/// targeted room, and passes when it has nothing to play or its play is rejected.
+struct Bot {
+    name: Arc<str>,
+    players: usize,
+    targeted: bool,
+    /// When the last card of the room was played, shared by every bot of the room.
+    last_play: Arc<Mutex<Option<Instant>>>,
+    stats: BotStats,
+}
+
+impl Bot {
+    async fn play(
+        mut self,
+        ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+        limit: Duration,
+    ) -> BotStats {
+        match timeout(limit, self.run(ws)).await {
+            Ok(Ok(())) => (),
+            Ok(Err(_)) => self.stats.disconnected = true,
+            Err(_) => self.stats.timed_out = true,
+        }
+        self.stats
+    }
+
+    async fn run(&mut self, ws: WebSocketStream<MaybeTlsStream<TcpStream>>) -> Result<()> {
+        let (mut sender, mut receiver) = ws.split();
+        send(
+            &mut sender,
+            PlayerAction::Hello {
+                protocol_version: PROTOCOL_VERSION,
+                client_name: Arc::from("loadtest"),
+                capabilities: Vec::new(),
+            },
+        )
+        .await?;
+        send(
+            &mut sender,
+            PlayerAction::Join {
+                name: self.name.clone(),
+            },
+        )
+        .await?;
+
+        let mut rng = StdRng::from_entropy();
+        let mut scores: Vec<Score> = Vec::new();
+        let mut joined = 0;
+        let mut host = false;
+        let mut started = false;
+        let mut my_turn = false;
+        let mut sent_at: Option<Instant> = None;
+        while let Some(frame) = receiver.next().await {
+            let Message::Text(text) = frame? else {
+                continue;
+            };
+            let msg: PlayerMessage = serde_json::from_str(&text)?;
+            // A play or pass is answered by the next turn, or by an error.
+            let answers = matches!(
+                msg,
+                PlayerMessage::RoundStart { .. }
+                    | PlayerMessage::NewRound { .. }
+                    | PlayerMessage::Error { .. }
+            );
+            if let Some(sent_at) = sent_at.take_if(|_| answers) {
+                self.stats.round_trips.push(sent_at.elapsed());
+            }
+            let action = match msg {
+                PlayerMessage::Joined { players_name } => {
+                    joined = players_name.len() + 1;
+                    None
+                }
+                PlayerMessage::NewPlayer { .. } => {
+                    joined += 1;
+                    None
+                }
+                PlayerMessage::HostStart => {
+                    host = true;
+                    None
+                }
+                PlayerMessage::Scoreboard { scores: new_scores } => {
+                    scores = new_scores;
+                    None
+                }
+                PlayerMessage::NewRound { cards, .. } => {
+                    my_turn = true;
+                    Some(if cards.is_empty() {
+                        PlayerAction::Pass
+                    } else {
+                        PlayerAction::UseCard {
+                            card_index: rng.gen_range(0..cards.len()),
+                            target: self
+                                .targeted
+                                .then(|| bot::choose_target(&scores, &self.name).cloned())
+                                .flatten(),
+                        }
+                    })
+                }
+                PlayerMessage::OtherUseCard { .. } => {
+                    if let Some(played_at) = *self.last_play.lock().expect("Should lock") {
+                        self.stats.broadcasts.push(played_at.elapsed());
+                    }
+                    None
+                }
+                PlayerMessage::Error { code, .. } => {
+                    let code = serde_json::to_value(code)?;
+                    *self
+                        .stats
+                        .errors
+                        .entry(code.as_str().unwrap_or_default().to_string())
+                        .or_default() += 1;
+                    my_turn.then_some(PlayerAction::Pass)
+                }
+                PlayerMessage::GameEnd { .. } => {
+                    self.stats.finished = true;
+                    None
+                }
+                PlayerMessage::GameEnded | PlayerMessage::GameStarted => break,
+                _ => None,
+            };
+            if host && !started && joined == self.players {
+                started = true;
+                send(&mut sender, PlayerAction::Start).await?;
+            }
+            if let Some(action) = action {
+                if matches!(action, PlayerAction::Pass) {
+                    my_turn = false;
+                } else {
+                    *self.last_play.lock().expect("Should lock") = Some(Instant::now());
+                }
+                sent_at = Some(Instant::now());
+                send(&mut sender, action).await?;
+            }
+        }
+        if !self.stats.finished {
+            bail!("Disconnected before the game ended");
+        }
+        Ok(())
+    }
+}
+
+async fn send(
+    sender: &mut SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
+    action: PlayerAction,
+) -> Result<()> {
+    sender
+        .send(Message::Text(serde_json::to_string(&action)?))
+        .await?;
+    Ok(())
+}
+
+#[derive(Default)]
+struct RoomOutcome {
+    created: bool,
+    connection_failures: usize,
+    bots: Vec<BotStats>,
+}
+
+async fn run_room(args: Arc<Args>) -> RoomOutcome {
+    let mut outcome = RoomOutcome::default();
+    let Ok(game_code) = client::create_game(&args.server, args.room.as_deref()).await else {
+        return outcome;
+    };
+    outcome.created = true;
+    let mut sockets = Vec::new();
+    for _ in 0..args.players {
+        match connect_async(format!("ws://{}/game/{game_code}", args.server)).await {
+            Ok((ws, _)) => sockets.push(ws),
+            Err(_) => outcome.connection_failures += 1,
+        }
+    }
+    let last_play = Arc::new(Mutex::new(None));
+    let players = sockets.len();
+    let bots = sockets
+        .into_iter()
+        .enumerate()
+        .map(|(seat, ws)| {
+            let bot = Bot {
+                name: format!("bot-{seat}").into(),
+                players,
+                targeted: args.targeted,
+                last_play: last_play.clone(),
+                stats: BotStats::default(),
+            };
+            tokio::spawn(bot.play(ws, args.timeout))
+        })
+        .collect::<Vec<_>>();
+    for bot in bots {
+        outcome
+            .bots
+            .push(bot.await.expect("Should finish without panicking"));
+    }
+    outcome
+}
+
+/// Latency percentiles, in milliseconds.
+#[derive(Serialize)]
+struct Percentiles {
+    samples: usize,
+    p50: f64,
+    p90: f64,
+    p99: f64,
+    max: f64,
+}
+
+impl Percentiles {
+    fn new(mut samples: Vec<Duration>) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+        let at = |percentile: f64| {
+            let rank = (percentile * samples.len() as f64).ceil() as usize;
+            samples[rank.clamp(1, samples.len()) - 1].as_secs_f64() * 1000.0
+        };
+        Some(Self {
+            samples: samples.len(),
+            p50: at(0.5),
+            p90: at(0.9),
+            p99: at(0.99),
+            max: at(1.0),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct Report {
+    rooms: usize,
+    players_per_room: usize,
+    elapsed_secs: f64,
+    rooms_created: usize,
+    games_finished: usize,
+    games_timed_out: usize,
+    connection_failures: usize,
+    disconnects: usize,
+    actions: usize,
+    actions_per_sec: f64,
+    /// Errors the server answered actions with, by code.
+    server_errors: BTreeMap<String, usize>,
+    round_trip_ms: Option<Percentiles>,
+    broadcast_ms: Option<Percentiles>,
+}
+
+impl Report {
+    fn new(args: &Args, outcomes: Vec<RoomOutcome>, elapsed: Duration) -> Self {
+        let mut report = Self {
+            rooms: args.rooms,
+            players_per_room: args.players,
+            elapsed_secs: elapsed.as_secs_f64(),
+            rooms_created: 0,
+            games_finished: 0,
+            games_timed_out: 0,
+            connection_failures: 0,
+            disconnects: 0,
+            actions: 0,
+            actions_per_sec: 0.0,
+            server_errors: BTreeMap::new(),
+            round_trip_ms: None,
+            broadcast_ms: None,
+        };
+        let mut round_trips = Vec::new();
+        let mut broadcasts = Vec::new();
+        for outcome in outcomes {
+            report.rooms_created += usize::from(outcome.created);
+            report.connection_failures += outcome.connection_failures;
+            report.games_finished += usize::from(outcome.bots.iter().any(|bot| bot.finished));
+            report.games_timed_out += usize::from(outcome.bots.iter().any(|bot| bot.timed_out));
+            for bot in outcome.bots {
+                report.disconnects += usize::from(bot.disconnected);
+                for (code, count) in bot.errors {
+                    *report.server_errors.entry(code).or_default() += count;
+                }
+                round_trips.extend(bot.round_trips);
+                broadcasts.extend(bot.broadcasts);
+            }
+        }
+        report.actions = round_trips.len();
+        report.actions_per_sec = report.actions as f64 / report.elapsed_secs;
+        report.round_trip_ms = Percentiles::new(round_trips);
+        report.broadcast_ms = Percentiles::new(broadcasts);
+        report
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Arc::new(Args::parse()?);
+    let permits = Arc::new(Semaphore::new(
+        args.concurrency.unwrap_or(args.rooms).max(1),
+    ));
+    let started = Instant::now();
+    let rooms = (0..args.rooms)
+        .map(|_| {
+            let args = args.clone();
+            let permits = permits.clone();
+            tokio::spawn(async move {
+                let _permit = permits.acquire_owned().await.expect("Should not be closed");
+                run_room(args).await
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut outcomes = Vec::new();
+    for room in rooms {
+        outcomes.push(room.await?);
+    }
+    let report = Report::new(&args, outcomes, started.elapsed());
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
@@ -0,0 +1,114 @@
+//! Balance simulator: `simulate [--games N] [--bots greedy,random,...] [--room JSON]
+//! [--card-set NAME] [--card-sets DIR] [--scoring RULE] [--max-rounds N] [--seed N]
+//! [--format json|csv]`.
+//!
+//! `--room` takes the same options as `POST /create-game`, `--card-set` and `--scoring`
+//! override them.
+
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chatroom_rust::{
+    card_set::CardSets,
+    game::{GameConfig, GameOptions},
+    scoring::Scoring,
+    simulation::{self, SimulationOptions},
+};
+use serde::de::DeserializeOwned;
+
+enum Format {
+    Json,
+    Csv,
+}
+
+struct Args {
+    options: SimulationOptions,
+    room: GameOptions,
+    card_sets: PathBuf,
+    card_set: Option<String>,
+    scoring: Option<Scoring>,
+    format: Format,
+}
+
+/// Parses a snake_case enum value the way room options spell it.
+fn parse_name<T: DeserializeOwned>(flag: &str, value: &str) -> Result<T> {
+    serde_json::from_value(serde_json::Value::from(value))
+        .with_context(|| format!("Invalid {flag} {value}"))
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut args = Self {
+            options: SimulationOptions::default(),
+            room: GameOptions::default(),
+            card_sets: std::env::var("CARD_SETS_DIR")
+                .unwrap_or_else(|_| "card_sets".to_string())
+                .into(),
+            card_set: None,
+            scoring: None,
+            format: Format::Json,
+        };
+        let mut argv = std::env::args().skip(1);
+        while let Some(arg) = argv.next() {
+            let value = argv.next().ok_or_else(|| anyhow!("{arg} needs a value"))?;
+            match arg.as_str() {
+                "--games" => args.options.games = value.parse().context("Invalid --games")?,
+                "--bots" => {
+                    args.options.strategies = value
+                        .split(',')
+                        .map(|bot| parse_name("--bots", bot.trim()))
+                        .collect::<Result<_>>()?;
+                }
+                "--room" => args.room = serde_json::from_str(&value).context("Invalid --room")?,
+                "--card-set" => args.card_set = Some(value),
+                "--card-sets" => args.card_sets = value.into(),
+                "--scoring" => args.scoring = Some(parse_name("--scoring", &value)?),
+                "--max-rounds" => {
+                    args.options.max_rounds = value.parse().context("Invalid --max-rounds")?
+                }
+                "--seed" => args.options.seed = value.parse().context("Invalid --seed")?,
+                "--format" => {
+                    args.format = match value.as_str() {
+                        "json" => Format::Json,
+                        "csv" => Format::Csv,
+                        _ => bail!("Unknown format {value}"),
+                    }
+                }
+                _ => bail!("Unknown argument {arg}"),
+            }
+        }
+        if args.options.strategies.len() < 2 {
+            bail!("--bots needs at least two bots");
+        }
+        if args.options.max_rounds == 0 {
+            bail!("--max-rounds should not be 0");
+        }
+        Ok(args)
+    }
+}
+
+fn main() -> Result<()> {
+    let mut args = Args::parse()?;
+    if let Some(card_set) = args.card_set {
+        args.room.card_set = Some(card_set);
+    }
+    if let Some(scoring) = args.scoring {
+        args.room.scoring = scoring;
+    }
+    let card_sets = CardSets::load(&args.card_sets)?;
+    let config = GameConfig::new(args.room, &card_sets)?;
+    // Bots are split between the teams like players joining a room.
+    if let Some(teams) = config.teams {
+        let bots = args.options.strategies.len();
+        config.check_team_sizes(
+            (0..teams.count)
+                .map(|team| bots / teams.count + usize::from(team < bots % teams.count)),
+        )?;
+    }
+    let report = simulation::simulate(&args.options, &config);
+    match args.format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        Format::Csv => print!("{}", report.to_csv()),
+    }
+    Ok(())
+}
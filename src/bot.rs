@@ -0,0 +1,61 @@
+//! Decisions shared by the bots that play against the server.
+
+use std::sync::Arc;
+
+use crate::player::Score;
+
+/// Picks the opponent a bot attacks in a targeted game: the one with the most points left,
+/// so the strongest player is worn down first. Teammates and players who are out are never
+/// picked.
+pub fn choose_target<'a>(scores: &'a [Score], own_name: &str) -> Option<&'a Arc<str>> {
+    let own_team = scores
+        .iter()
+        .find(|score| &*score.name == own_name)
+        .and_then(|score| score.team);
+    scores
+        .iter()
+        .filter(|score| &*score.name != own_name)
+        .filter(|score| own_team.is_none() || score.team != own_team)
+        .filter_map(|score| Some((score.point?, &score.name)))
+        .max_by_key(|(point, _)| *point)
+        .map(|(_, name)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(name: &str, point: Option<i32>, team: Option<usize>) -> Score {
+        Score {
+            name: name.into(),
+            point,
+            hand_size: None,
+            team,
+        }
+    }
+
+    #[test]
+    fn targets_the_strongest_opponent() {
+        let scores = [
+            score("me", Some(20), None),
+            score("weak", Some(3), None),
+            score("strong", Some(9), None),
+            score("out", None, None),
+        ];
+        assert_eq!(
+            choose_target(&scores, "me").map(|name| &**name),
+            Some("strong")
+        );
+
+        let scores = [
+            score("me", Some(5), Some(0)),
+            score("mate", Some(30), Some(0)),
+            score("rival", Some(2), Some(1)),
+        ];
+        assert_eq!(
+            choose_target(&scores, "me").map(|name| &**name),
+            Some("rival")
+        );
+        assert_eq!(choose_target(&scores[..2], "me"), None);
+    }
+}
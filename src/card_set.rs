@@ -0,0 +1,336 @@
+use std::{collections::BTreeMap, fs, path::Path, sync::Arc};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::stack::CardDistribution;
+
+/// Name of the built-in card set, used when a room does not pick one.
+pub const DEFAULT_CARD_SET: &str = "default";
+
+/// Weights describing how cards are generated, loaded from a TOML or JSON file.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct CardSet {
+    /// Weight of a card having 1, 2, 3, ... actions.
+    pub actions_per_card: Vec<u32>,
+    /// Weight of each kind of action.
+    pub action_weights: ActionWeights,
+    /// Numbers pushed by `push`.
+    pub push: ValueRange,
+    /// Numbers added by `add`.
+    pub add: ValueRange,
+    /// Factors used by `mul`.
+    #[serde(default = "ValueRange::default_mul")]
+    pub mul: ValueRange,
+    /// Steps moved by `rotate`, positive towards the bottom.
+    #[serde(default = "ValueRange::default_rotate")]
+    pub rotate: ValueRange,
+    /// Weight of a card carrying each power, or none.
+    #[serde(default)]
+    pub powers: PowerWeights,
+}
+
+/// Weight of each [`crate::stack::Power`] on a card, against `none` for a plain card.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PowerWeights {
+    pub none: u32,
+    pub skip: u32,
+    pub reverse: u32,
+    pub steal: u32,
+    pub peek: u32,
+    pub shield: u32,
+}
+
+impl Default for PowerWeights {
+    fn default() -> Self {
+        Self {
+            none: 1,
+            skip: 0,
+            reverse: 0,
+            steal: 0,
+            peek: 0,
+            shield: 0,
+        }
+    }
+}
+
+impl PowerWeights {
+    /// The weights in the order [`crate::stack::CardDistribution`] samples them.
+    pub fn weights(&self) -> [u32; 6] {
+        [
+            self.none,
+            self.skip,
+            self.reverse,
+            self.steal,
+            self.peek,
+            self.shield,
+        ]
+    }
+}
+
+/// Weight of each kind of action. Actions added after the first release default to 0, so
+/// older card set files keep dealing the same cards.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ActionWeights {
+    pub push: u32,
+    pub pop: u32,
+    pub reverse: u32,
+    pub add: u32,
+    pub neg: u32,
+    #[serde(default)]
+    pub dup: u32,
+    #[serde(default)]
+    pub swap: u32,
+    #[serde(default)]
+    pub mul: u32,
+    #[serde(default)]
+    pub rotate: u32,
+    #[serde(default)]
+    pub clear: u32,
+    #[serde(default)]
+    pub sort: u32,
+}
+
+/// A weighted range of numbers `min, min + 1, ...`, one weight per number, which is then
+/// negated with weight `negate` against `keep_sign`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ValueRange {
+    pub min: i32,
+    pub weights: Vec<u32>,
+    pub keep_sign: u32,
+    pub negate: u32,
+}
+
+impl Default for CardSet {
+    fn default() -> Self {
+        Self {
+            actions_per_card: vec![4, 6, 3, 1],
+            action_weights: ActionWeights {
+                push: 7,
+                pop: 3,
+                reverse: 3,
+                add: 2,
+                neg: 1,
+                dup: 0,
+                swap: 0,
+                mul: 0,
+                rotate: 0,
+                clear: 0,
+                sort: 0,
+            },
+            push: ValueRange {
+                min: -9,
+                weights: vec![1, 1, 1, 2, 3, 4, 8, 8, 8, 1],
+                keep_sign: 1,
+                negate: 7,
+            },
+            add: ValueRange {
+                min: 1,
+                weights: vec![1, 2, 3, 3],
+                keep_sign: 1,
+                negate: 1,
+            },
+            mul: ValueRange::default_mul(),
+            rotate: ValueRange::default_rotate(),
+            powers: PowerWeights::default(),
+        }
+    }
+}
+
+impl CardSet {
+    /// Parses a card set, as TOML if `path` ends in `.toml` and as JSON otherwise.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Cannot read card set {}", path.display()))?;
+        let card_set: CardSet = if path.extension().is_some_and(|ext| ext == "toml") {
+            toml::from_str(&text)?
+        } else {
+            serde_json::from_str(&text)?
+        };
+        card_set
+            .validate()
+            .with_context(|| format!("Invalid card set {}", path.display()))?;
+        Ok(card_set)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        check_weights("actions_per_card", &self.actions_per_card)?;
+        let ActionWeights {
+            push,
+            pop,
+            reverse,
+            add,
+            neg,
+            dup,
+            swap,
+            mul,
+            rotate,
+            clear,
+            sort,
+        } = self.action_weights;
+        check_weights(
+            "action_weights",
+            &[
+                push, pop, reverse, add, neg, dup, swap, mul, rotate, clear, sort,
+            ],
+        )?;
+        self.push.validate("push")?;
+        self.add.validate("add")?;
+        self.mul.validate("mul")?;
+        self.rotate.validate("rotate")?;
+        check_weights("powers", &self.powers.weights())?;
+        Ok(())
+    }
+}
+
+impl ValueRange {
+    /// Doubles or triples, keeping the sign.
+    fn default_mul() -> Self {
+        Self {
+            min: 2,
+            weights: vec![1, 1],
+            keep_sign: 1,
+            negate: 0,
+        }
+    }
+
+    /// One or two steps either way.
+    fn default_rotate() -> Self {
+        Self {
+            min: 1,
+            weights: vec![1, 1],
+            keep_sign: 1,
+            negate: 1,
+        }
+    }
+
+    fn validate(&self, name: &str) -> Result<()> {
+        check_weights(&format!("{name}.weights"), &self.weights)?;
+        check_weights(
+            &format!("{name}.keep_sign/negate"),
+            &[self.keep_sign, self.negate],
+        )?;
+        let len = i32::try_from(self.weights.len())?;
+        if self.min.checked_add(len).is_none() || self.min == i32::MIN {
+            bail!("{name} range starting at {} is out of bounds", self.min);
+        }
+        Ok(())
+    }
+}
+
+fn check_weights(name: &str, weights: &[u32]) -> Result<()> {
+    if weights.iter().all(|weight| *weight == 0) {
+        bail!("{name} needs at least one non-zero weight");
+    }
+    if weights.iter().map(|weight| u64::from(*weight)).sum::<u64>() > u64::from(u32::MAX) {
+        bail!("{name} weights add up to more than {}", u32::MAX);
+    }
+    Ok(())
+}
+
+/// Every card set a room can choose from, by name.
+#[derive(Debug, Clone)]
+pub struct CardSets {
+    sets: BTreeMap<String, Arc<CardDistribution>>,
+}
+
+impl Default for CardSets {
+    fn default() -> Self {
+        Self {
+            sets: BTreeMap::from([(
+                DEFAULT_CARD_SET.to_string(),
+                Arc::new(CardDistribution::default()),
+            )]),
+        }
+    }
+}
+
+impl CardSets {
+    /// Loads every `.toml` and `.json` file in `dir` next to the built-in default set, named
+    /// after the file stem. A missing directory only leaves the default set.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let mut card_sets = Self::default();
+        if !dir.exists() {
+            return Ok(card_sets);
+        }
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !path
+                .extension()
+                .is_some_and(|ext| ext == "toml" || ext == "json")
+            {
+                continue;
+            }
+            let name = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .ok_or_else(|| anyhow!("Invalid card set file name {}", path.display()))?
+                .to_string();
+            let distribution = CardDistribution::new(&CardSet::load(&path)?)?;
+            card_sets.sets.insert(name, Arc::new(distribution));
+        }
+        Ok(card_sets)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<CardDistribution>> {
+        self.sets.get(name).cloned()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.sets.keys().map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_default_matches_builtin() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("card_sets/default.toml");
+        assert_eq!(CardSet::load(&path).unwrap(), CardSet::default());
+    }
+
+    #[test]
+    fn rejects_invalid_card_sets() {
+        let card_set = CardSet {
+            actions_per_card: vec![0, 0],
+            ..CardSet::default()
+        };
+        assert!(card_set.validate().is_err());
+
+        let mut card_set = CardSet::default();
+        card_set.push.weights.clear();
+        assert!(card_set.validate().is_err());
+
+        let mut card_set = CardSet::default();
+        card_set.add.keep_sign = 0;
+        card_set.add.negate = 0;
+        assert!(card_set.validate().is_err());
+
+        let mut card_set = CardSet::default();
+        card_set.push.min = i32::MAX;
+        assert!(card_set.validate().is_err());
+
+        assert!(toml::from_str::<CardSet>("actions_per_card = [1]\nextra = 1").is_err());
+
+        let mut card_set = CardSet::default();
+        card_set.rotate.weights = vec![0];
+        assert!(card_set.validate().is_err());
+    }
+
+    #[test]
+    fn older_card_sets_leave_new_actions_off() {
+        let text = r#"
+            actions_per_card = [4, 6, 3, 1]
+            action_weights = { push = 7, pop = 3, reverse = 3, add = 2, neg = 1 }
+            push = { min = -9, weights = [1, 1, 1, 2, 3, 4, 8, 8, 8, 1], keep_sign = 1, negate = 7 }
+            add = { min = 1, weights = [1, 2, 3, 3], keep_sign = 1, negate = 1 }
+        "#;
+        assert_eq!(toml::from_str::<CardSet>(text).unwrap(), CardSet::default());
+    }
+}
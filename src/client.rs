@@ -0,0 +1,43 @@
+//! HTTP calls to a running server, shared by the terminal client and the load tester.
+
+use anyhow::{anyhow, bail, Result};
+use hyper::{body, Body, Client, Method, Request};
+use serde_json::Value;
+
+/// Creates a room with `POST /create-game` and returns its code; `options` is the JSON body,
+/// sent as given, the default room without it.
+pub async fn create_game(server: &str, options: Option<&str>) -> Result<String> {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("http://{server}/create-game"))
+        .body(options.map_or_else(Body::empty, |options| Body::from(options.to_string())))?;
+    let response = request_json(request).await?;
+    response["game_code"]
+        .as_str()
+        .map(ToString::to_string)
+        .ok_or_else(|| anyhow!("Unexpected response: {response}"))
+}
+
+/// Asks `GET /game-exist/{code}` whether the room exists.
+pub async fn game_exist(server: &str, game_code: &str) -> Result<bool> {
+    let request = Request::builder()
+        .uri(format!("http://{server}/game-exist/{game_code}"))
+        .body(Body::empty())?;
+    let response = request_json(request).await?;
+    response["game_exist"]
+        .as_bool()
+        .ok_or_else(|| anyhow!("Unexpected response: {response}"))
+}
+
+async fn request_json(request: Request<Body>) -> Result<Value> {
+    let response = Client::new().request(request).await?;
+    let status = response.status();
+    let bytes = body::to_bytes(response.into_body()).await?;
+    if !status.is_success() {
+        bail!(
+            "Server responded with {status}: {}",
+            String::from_utf8_lossy(&bytes)
+        );
+    }
+    Ok(serde_json::from_slice(&bytes)?)
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+
+use rand::{seq::SliceRandom, Rng};
+use serde::Deserialize;
+
+use crate::stack::{Card, CardDistribution};
+
+/// Room settings for playing from a finite deck instead of fresh random cards every turn.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeckOptions {
+    /// Number of cards dealt from the card set when the game starts.
+    pub size: usize,
+    /// Number of cards a player holds, drawn back up after every play.
+    pub hand_size: usize,
+}
+
+impl Default for DeckOptions {
+    fn default() -> Self {
+        Self {
+            size: 60,
+            hand_size: 3,
+        }
+    }
+}
+
+/// A draw pile, a discard pile and the hand of every player.
+///
+/// Played cards go to the discard pile, which is shuffled back into the draw pile once it
+/// runs out, so no card is ever lost.
+#[derive(Debug)]
+pub struct Deck {
+    draw_pile: Vec<Card>,
+    discard_pile: Vec<Card>,
+    hands: BTreeMap<usize, Vec<Card>>,
+    hand_size: usize,
+}
+
+impl Deck {
+    pub fn new(options: DeckOptions, cards: &CardDistribution, rng: &mut impl Rng) -> Self {
+        let mut draw_pile = (0..options.size)
+            .map(|_| rng.sample(cards))
+            .collect::<Vec<_>>();
+        draw_pile.shuffle(rng);
+        Self {
+            draw_pile,
+            discard_pile: Vec::new(),
+            hands: BTreeMap::new(),
+            hand_size: options.hand_size,
+        }
+    }
+
+    pub fn hand(&self, id: &usize) -> &[Card] {
+        self.hands.get(id).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn hand_size(&self, id: &usize) -> usize {
+        self.hand(id).len()
+    }
+
+    /// Draws cards into the hand of `id` until it is full or no card is left anywhere.
+    pub fn draw(&mut self, id: usize, rng: &mut impl Rng) {
+        let hand = self.hands.entry(id).or_default();
+        while hand.len() < self.hand_size {
+            if self.draw_pile.is_empty() {
+                if self.discard_pile.is_empty() {
+                    break;
+                }
+                self.draw_pile.append(&mut self.discard_pile);
+                self.draw_pile.shuffle(rng);
+            }
+            hand.extend(self.draw_pile.pop());
+        }
+    }
+
+    /// Moves the card at `index` from the hand of `id` to the discard pile.
+    pub fn play(&mut self, id: &usize, index: usize) -> Option<Card> {
+        let hand = self.hands.get_mut(id)?;
+        if index >= hand.len() {
+            return None;
+        }
+        let card = hand.remove(index);
+        self.discard_pile.push(card.clone());
+        Some(card)
+    }
+
+    /// Discards the hand of a player who left or lost.
+    pub fn remove_player(&mut self, id: &usize) {
+        if let Some(hand) = self.hands.remove(id) {
+            self.discard_pile.extend(hand);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn hands_persist_and_cards_are_recycled() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let options = DeckOptions {
+            size: 4,
+            hand_size: 3,
+        };
+        let mut deck = Deck::new(options, &CardDistribution::default(), &mut rng);
+        deck.draw(1, &mut rng);
+        deck.draw(2, &mut rng);
+        assert_eq!((deck.hand_size(&1), deck.hand_size(&2)), (3, 1));
+
+        let kept = deck.hand(&1)[1..].to_vec();
+        let played = deck.play(&1, 0).unwrap();
+        assert_eq!(deck.hand(&1), kept);
+        assert!(deck.play(&1, 2).is_none());
+
+        deck.draw(1, &mut rng);
+        assert_eq!(deck.hand(&1)[..2], kept);
+        assert_eq!(deck.hand(&1)[2], played);
+
+        deck.remove_player(&1);
+        deck.draw(2, &mut rng);
+        assert_eq!(deck.hand_size(&2), 3);
+        assert_eq!(deck.hand_size(&1), 0);
+    }
+}
@@ -1,4 +1,11 @@
-use std::{collections::BTreeMap, sync::{Arc, LazyLock}, time::Duration};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, LazyLock,
+    },
+    time::Duration,
+};
 
 use futures::{stream_select, Future, Stream, StreamExt};
 use rand::{rngs::StdRng, Rng, SeedableRng};
@@ -10,11 +17,17 @@ use tokio::{
 use tokio_stream::wrappers::{IntervalStream, ReceiverStream};
 
 use crate::{
-    player::{Player, PlayerAction, PlayerMessage},
-    stack::{Card, CardDistribution, Overflow, Stack},
+    card_set::{CardSets, DEFAULT_CARD_SET},
+    deck::DeckOptions,
+    player::{ErrorCode, Player, PlayerAction, PlayerMessage, PointChange},
+    scoring::Scoring,
+    stack::CardDistribution,
+    state::{Event, GameState, STACK_LEN, STARTING_POINTS},
+    team::{TeamOptions, TeamPoints, Teams},
 };
 
 use anyhow::{anyhow, Result};
+use serde::Deserialize;
 
 static RNG: LazyLock<Mutex<StdRng>> = LazyLock::new(|| Mutex::new(StdRng::from_entropy()));
 
@@ -22,10 +35,180 @@ pub enum ExternalMessage {
     NewPlayer(Player),
 }
 
+/// Room settings a client may pass as the JSON body of `POST /create-game`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameOptions {
+    /// Name of the card set to deal from, see [`CardSets`].
+    pub card_set: Option<String>,
+    /// How a full stack is scored.
+    pub scoring: Scoring,
+    /// Play from a finite deck with persistent hands instead of three fresh cards a turn.
+    pub deck: Option<DeckOptions>,
+    /// Cards a player may play or discard before the turn passes on.
+    pub plays_per_turn: usize,
+    /// Points lost for every discarded card.
+    pub discard_cost: i32,
+    /// Split players into teams that win or lose together.
+    pub teams: Option<TeamOptions>,
+    /// Every card played names the opponent who absorbs its overflows.
+    pub targeted: bool,
+    /// End the game once a player, or team, climbs to this many points; the best of those who
+    /// did wins. Every side has to start below it.
+    pub point_target: Option<i32>,
+    /// End the game after this many rounds, the highest score winning.
+    pub round_limit: Option<usize>,
+    /// Shrink the stack every round after a while, so that overflows come faster.
+    pub sudden_death: Option<SuddenDeath>,
+}
+
+/// Room settings for the sudden-death phase.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SuddenDeath {
+    /// Rounds played before the stack starts shrinking.
+    pub after_rounds: usize,
+    /// The stack stops shrinking at this length.
+    pub min_len: usize,
+}
+
+impl Default for SuddenDeath {
+    fn default() -> Self {
+        Self {
+            after_rounds: 10,
+            min_len: 3,
+        }
+    }
+}
+
+impl Default for GameOptions {
+    fn default() -> Self {
+        Self {
+            card_set: None,
+            scoring: Scoring::default(),
+            deck: None,
+            plays_per_turn: 1,
+            discard_cost: 1,
+            teams: None,
+            targeted: false,
+            point_target: None,
+            round_limit: None,
+            sudden_death: None,
+        }
+    }
+}
+
+/// The rules a game is played with, resolved from [`GameOptions`].
+#[derive(Debug, Clone)]
+pub struct GameConfig {
+    pub cards: Arc<CardDistribution>,
+    pub scoring: Scoring,
+    pub deck: Option<DeckOptions>,
+    pub plays_per_turn: usize,
+    pub discard_cost: i32,
+    pub teams: Option<TeamOptions>,
+    pub targeted: bool,
+    pub point_target: Option<i32>,
+    pub round_limit: Option<usize>,
+    pub sudden_death: Option<SuddenDeath>,
+}
+
+impl GameConfig {
+    pub fn new(options: GameOptions, card_sets: &CardSets) -> Result<Self> {
+        let card_set = options.card_set.as_deref().unwrap_or(DEFAULT_CARD_SET);
+        let cards = card_sets
+            .get(card_set)
+            .ok_or_else(|| anyhow!("Unknown card set: {card_set}"))?;
+        if let Some(deck) = options.deck {
+            if deck.size == 0 || deck.hand_size == 0 {
+                return Err(anyhow!("Deck and hand size should not be 0"));
+            }
+        }
+        if options.plays_per_turn == 0 {
+            return Err(anyhow!("Plays per turn should not be 0"));
+        }
+        if options.discard_cost < 0 {
+            return Err(anyhow!("Discard cost should not be negative"));
+        }
+        if options.teams.is_some_and(|teams| teams.count < 2) {
+            return Err(anyhow!("There should be at least 2 teams"));
+        }
+        if options
+            .point_target
+            .is_some_and(|target| target <= STARTING_POINTS)
+        {
+            return Err(anyhow!(
+                "Point target should be above the {STARTING_POINTS} starting points"
+            ));
+        }
+        if options.round_limit == Some(0) {
+            return Err(anyhow!("Round limit should not be 0"));
+        }
+        if options
+            .sudden_death
+            .is_some_and(|sudden_death| sudden_death.min_len < 2)
+        {
+            return Err(anyhow!("Sudden death should keep a stack of at least 2"));
+        }
+        if options
+            .sudden_death
+            .is_some_and(|sudden_death| sudden_death.min_len > STACK_LEN)
+        {
+            return Err(anyhow!(
+                "Sudden death should not keep a stack longer than {STACK_LEN}"
+            ));
+        }
+        Ok(Self {
+            cards,
+            scoring: options.scoring,
+            deck: options.deck,
+            plays_per_turn: options.plays_per_turn,
+            discard_cost: options.discard_cost,
+            teams: options.teams,
+            targeted: options.targeted,
+            point_target: options.point_target,
+            round_limit: options.round_limit,
+            sudden_death: options.sudden_death,
+        })
+    }
+}
+
+impl GameConfig {
+    /// Checks that teams of `team_sizes` players all start below the point target, a summed
+    /// team starting with the points of every member.
+    pub fn check_team_sizes(&self, team_sizes: impl IntoIterator<Item = usize>) -> Result<()> {
+        let Some(target) = self.point_target else {
+            return Ok(());
+        };
+        if self
+            .teams
+            .is_none_or(|teams| teams.points != TeamPoints::Summed)
+        {
+            return Ok(());
+        }
+        for size in team_sizes {
+            let points = STARTING_POINTS.saturating_mul(size.try_into().unwrap_or(i32::MAX));
+            if points >= target {
+                return Err(anyhow!(
+                    "A team of {size} starts with {points} points, the point target {target} should be above it"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self::new(GameOptions::default(), &CardSets::default()).expect("Should success")
+    }
+}
+
 #[derive(Debug)]
 pub struct Game {
     _handle: JoinHandle<()>,
     pub action_sender: MpscSender<(PlayerAction, usize)>,
+    next_player_id: AtomicUsize,
 }
 
 enum Message {
@@ -34,13 +217,17 @@ enum Message {
 }
 
 impl Game {
-    pub fn new<F>(game_code: String, remover: impl FnOnce() -> F + Send + 'static) -> Self
+    pub fn new<F>(
+        game_code: String,
+        config: GameConfig,
+        remover: impl FnOnce() -> F + Send + 'static,
+    ) -> Self
     where
         F: Future<Output = ()> + Send,
     {
         let (action_sender, action_receiver) = tokio::sync::mpsc::channel(3);
         let handle = tokio::spawn(async move {
-            let mut data = GameData::new(game_code);
+            let mut data = GameData::new(game_code, config);
             let game_func = || async {
                 let mut message_stream = stream_select!(
                     ReceiverStream::new(action_receiver)
@@ -52,23 +239,30 @@ impl Game {
                     .map(|_| Message::CheckAlive)
                 );
                 Self::waiting_for_start(&mut data, &mut message_stream).await?;
-                let mut game_data = Self::start(&mut data).await?;
-                Self::game_loop(&mut data, &mut game_data, &mut message_stream).await?;
-                Self::game_end(&mut game_data, &mut data).await?;
+                let mut state = Self::start(&mut data).await;
+                Self::game_loop(&mut data, &mut state, &mut message_stream).await?;
                 Ok::<(), anyhow::Error>(())
             };
             if let Err(err) = game_func().await {
                 eprintln!("ERROR: {err}");
             }
-            Self::clean_up(&mut data).await;
+            Self::clean_up(&mut data);
             remover().await;
         });
         Self {
             _handle: handle,
             action_sender,
+            next_player_id: AtomicUsize::new(1),
         }
     }
 
+    /// Hands a new connection the id it sends every action with, so that even a player who
+    /// leaves before joining is removed by id.
+    pub fn add_player(&self) -> (usize, MpscSender<(PlayerAction, usize)>) {
+        let id = self.next_player_id.fetch_add(1, Ordering::Relaxed);
+        (id, self.action_sender.clone())
+    }
+
     async fn waiting_for_start(
         data: &mut GameData,
         message_stream: &mut (impl Stream<Item = Message> + Unpin),
@@ -76,52 +270,117 @@ impl Game {
         while let Some(message) = message_stream.next().await {
             match message {
                 Message::CheckAlive => {
+                    if !data.remove_closed().is_empty() {
+                        data.send_teams();
+                    }
                     if data.players.is_empty() {
                         return Err(anyhow!("Game Not Alive: {}", data.code()));
-                    };
+                    }
                 }
                 Message::Internal(PlayerAction::Join { .. }, _ ) => {
                     return Err(anyhow!("Join Action should not be sent"));
                 }
-                Message::Internal(PlayerAction::JoinWithPlayer { player: new_player, name }, _) => {
-                    let id = *data
-                        .players
-                        .last_key_value()
-                        .map(|(id, _)| id)
-                        .unwrap_or(&0)
-                        + 1;
+                Message::Internal(PlayerAction::JoinWithPlayer { player: new_player, name }, id) => {
+                    if new_player.is_closed() {
+                        continue;
+                    }
+                    let rejection = if name.trim().is_empty() {
+                        Some("Name should not be empty".to_string())
+                    } else if data.all_players_name().any(|taken| taken == name) {
+                        Some(format!("{name} is already taken"))
+                    } else {
+                        None
+                    };
+                    if let Some(message) = rejection {
+                        new_player.send(PlayerMessage::error(
+                            ErrorCode::InvalidName,
+                            message,
+                            Some("join".into()),
+                        ));
+                        continue;
+                    }
                     for player in data.all_players() {
-                        player
-                            .send(PlayerMessage::NewPlayer {
-                                name: name.clone(),
-                            })
-                            .await?;
+                        player.send(PlayerMessage::NewPlayer { name: name.clone() });
                     }
-                    new_player.send(PlayerMessage::Register(id)).await?;
-                    new_player
-                        .send(PlayerMessage::Joined {
-                            players_name: data
-                                .all_players_name()
-                                .collect(),
-                        })
-                        .await?;
+                    new_player.send(PlayerMessage::Joined {
+                        players_name: data.all_players_name().collect(),
+                    });
                     if data.players.is_empty() {
-                        new_player.send(PlayerMessage::HostStart).await?;
+                        new_player.send(PlayerMessage::HostStart);
                     }
                     data.players.insert(id, (new_player, name));
+                    if let Some(teams) = &mut data.teams {
+                        teams.join(id);
+                    }
+                    data.send_teams();
                 }
-                Message::Internal(PlayerAction::Start, id) => {
-                    if data.players.len() > 1 {
+                Message::Internal(PlayerAction::Start, id) if !data.players.contains_key(&id) => {}
+                Message::Internal(action @ PlayerAction::Start, id) => {
+                    let team_sizes = data.teams.as_ref().map_or_else(Vec::new, |teams| {
+                        (0..data.config.teams.map_or(0, |options| options.count))
+                            .map(|team| teams.members(team).count())
+                            .collect()
+                    });
+                    if let Err(err) = data.config.check_team_sizes(team_sizes) {
+                        data.reject(&id, &action, ErrorCode::IllegalInPhase, err.to_string());
+                    } else if data.players.len() > 1
+                        && data
+                            .teams
+                            .as_ref()
+                            .is_none_or(|teams| teams.non_empty() > 1)
+                    {
                         break;
                     } else {
-                        data.send_player(&id, PlayerMessage::StartFailed).await?;
+                        data.send_player(&id, PlayerMessage::StartFailed)?;
                     }
                 }
-                Message::Internal(PlayerAction::Quit, id) => {
+                Message::Internal(action @ (PlayerAction::Quit | PlayerAction::Error(_)), id) => {
+                    if let PlayerAction::Error(err) = action {
+                        eprintln!("ERROR: {err}");
+                    }
                     data.players.remove(&id);
                     if data.players.is_empty() {
                        return Err(anyhow!("Player all quit: {}", data.code()));
                     }
+                    if let Some(teams) = &mut data.teams {
+                        teams.leave(&id);
+                    }
+                    data.send_teams();
+                }
+                Message::Internal(PlayerAction::PickTeam { .. }, id)
+                    if !data.players.contains_key(&id) => {}
+                Message::Internal(action @ PlayerAction::PickTeam { team }, id) => {
+                    let picked = data.teams.as_mut().map(|teams| teams.pick(id, team));
+                    match picked {
+                        Some(true) => data.send_teams(),
+                        Some(false) => data.reject(
+                            &id,
+                            &action,
+                            ErrorCode::MalformedAction,
+                            format!("There is no team {team}"),
+                        ),
+                        None => data.reject(
+                            &id,
+                            &action,
+                            ErrorCode::IllegalInPhase,
+                            "This room is not played in teams",
+                        ),
+                    }
+                }
+                Message::Internal(PlayerAction::Chat { text }, id) => data.chat(&id, text),
+                Message::Internal(
+                    action @ (PlayerAction::UseCard { .. }
+                    | PlayerAction::Discard { .. }
+                    | PlayerAction::Preview { .. }
+                    | PlayerAction::Pass),
+                    id,
+                ) => {
+                    data.reject(
+                        &id,
+                        &action,
+                        ErrorCode::IllegalInPhase,
+                        "Game has not started",
+                    );
                 }
                 _ => (),
             }
@@ -129,165 +388,66 @@ impl Game {
         Ok(())
     }
 
-    async fn start(data: &mut GameData) -> Result<InGameData> {
-        let mut game_data = InGameData::new();
-
-        for (id, player) in data.all_players_and_ids() {
-            game_data
-                .player_state
-                .insert(id, PlayerState { point: 10 });
-
-            player.send(PlayerMessage::Start { point: 10 }).await?;
-        }
-        Ok(game_data)
+    async fn start(data: &mut GameData) -> GameState {
+        let seed = RNG.lock().await.gen();
+        let names = data
+            .players
+            .iter()
+            .map(|(id, (_, name))| (*id, name.clone()))
+            .collect();
+        let (state, events) = GameState::new(data.config.clone(), names, data.teams.clone(), seed);
+        data.deliver(events);
+        state
     }
 
     async fn game_loop(
         player_data: &mut GameData,
-        game_data: &mut InGameData,
+        state: &mut GameState,
         message_stream: &mut (impl Stream<Item = Message> + Unpin),
     ) -> Result<()> {
-        let mut playing_id = *game_data
-            .player_state
-            .keys()
-            .nth(RNG.lock().await.gen_range(0..game_data.player_state.len()))
-            .unwrap();
-        while !game_data.game_ended() {
-            let cards = game_data.gen_cards().await;
-            let playing_player_name = player_data
-                .get_player_name(&playing_id)
-                .ok_or_else(|| anyhow!("Should be in player_data"))?;
-            for (id, player) in player_data.all_players_and_ids() {
-                player
-                    .send(PlayerMessage::RoundStart {
-                        player_name: playing_player_name.clone(),
-                        stack: game_data.stack.clone(),
-                        point: game_data.get_state(&id).map(|data| data.point),
-                    })
-                    .await?;
-                if playing_id == id {
-                    player
-                        .send(PlayerMessage::NewRound {
-                            cards: cards.clone(),
-                            stack: game_data.stack.clone(),
-                        })
-                        .await?;
-                }
-            }
-            while let Some(message) = message_stream.next().await {
-                match message {
-                    Message::CheckAlive => (),
-                    Message::Internal(PlayerAction::Join { .. }, _) => {
-                        return Err(anyhow!("Join Action should not be sent"));
-                    }
-                    Message::Internal(PlayerAction::JoinWithPlayer { player, .. }, _) => {
-                        player.send(PlayerMessage::GameStarted).await?;
-                    }
-                    Message::Internal(PlayerAction::Start, id) => {
-                        player_data
-                            .send_player(&id, PlayerMessage::GameStarted)
-                            .await?;
+        while let Some(message) = message_stream.next().await {
+            match message {
+                Message::CheckAlive => {
+                    for id in player_data.remove_closed() {
+                        player_data.deliver(state.leave(id));
                     }
-                    Message::Internal(PlayerAction::UseCard { card_index }, id)
-                        if id == playing_id =>
-                    {
-                        let card = &cards[card_index];
-                        let overflows = game_data.stack.use_card(card);
-                        if !overflows.is_empty() {
-                            Self::handle_overflow(overflows, playing_id, player_data, game_data)
-                                .await?;
-                        }
-                        for (id, player) in player_data.all_players_and_ids() {
-                            if playing_id != id {
-                                player
-                                    .send(PlayerMessage::OtherUseCard { card: card.clone() })
-                                    .await?;
-                            }
-                        }
-                        playing_id = game_data.next_id(playing_id);
-                        break;
+                    if player_data.players.is_empty() {
+                        return Err(anyhow!("All player quit"));
                     }
-                    Message::Internal(PlayerAction::UseCard { .. }, id) => {
-                        player_data
-                            .send_player(&id, PlayerMessage::InvalidOperation)
-                            .await?;
+                }
+                Message::Internal(PlayerAction::Join { .. }, _) => {
+                    return Err(anyhow!("Join Action should not be sent"));
+                }
+                Message::Internal(PlayerAction::JoinWithPlayer { player, .. }, _) => {
+                    player.send(PlayerMessage::GameStarted);
+                }
+                Message::Internal(PlayerAction::Chat { text }, id) => {
+                    player_data.chat(&id, text);
+                }
+                Message::Internal(action @ (PlayerAction::Quit | PlayerAction::Error(_)), id) => {
+                    if let PlayerAction::Error(err) = action {
+                        eprintln!("ERROR: {err}");
                     }
-                    Message::Internal(PlayerAction::Quit, id) => {
-                        player_data.players.remove(&id);
-                        game_data.player_state.remove(&id);
-                        if !player_data.players.is_empty() && game_data.player_state.is_empty() {
-                            break;
-                        } else if player_data.players.is_empty() {
-                            return Err(anyhow!("All player quit"));
-                        }
+                    player_data.players.remove(&id);
+                    if player_data.players.is_empty() {
+                        return Err(anyhow!("All player quit"));
                     }
-                    _ => (),
+                    player_data.deliver(state.leave(id));
+                }
+                Message::Internal(action, id) => {
+                    player_data.deliver(state.apply(id, &action));
                 }
             }
-        }
-        Ok(())
-    }
-
-    async fn handle_overflow(
-        overflows: Vec<Overflow>,
-        playing_id: usize,
-        player_data: &mut GameData,
-        game_data: &mut InGameData,
-    ) -> Result<()> {
-        let (gain, lose) = overflows
-            .into_iter()
-            .fold((0, 0), |(gain, lose), overflow| {
-                (gain + overflow.self_gain, lose + overflow.other_lost)
-            });
-        let mut lost_players = Vec::new();
-        game_data.player_state.retain(|id, state| {
-            if playing_id == *id {
-                state.point += gain;
-            } else {
-                state.point -= lose;
-            }
-            if state.point <= 0 {
-                lost_players.push(
-                    player_data
-                        .get_player(id)
-                        .ok_or(anyhow!("Should be in player")),
-                );
+            if state.is_over() {
+                return Ok(());
             }
-            state.point > 0
-        });
-        for lose in lost_players {
-            lose?.send(PlayerMessage::Lose).await?;
         }
-        Ok(())
+        Err(anyhow!("Game should end"))
     }
 
-    async fn game_end(game_data: &mut InGameData, player_data: &mut GameData) -> Result<()> {
-        if !game_data.game_ended() {
-            return Err(anyhow!("Game should end"));
-        }
-        let winner_id = game_data.player_state.first_key_value();
-        let winner_name = winner_id.and_then(|(id, _)| player_data.get_player_name(id));
-        if let Some((id, _)) = winner_id {
-            player_data
-                .send_player(id, PlayerMessage::Win)
-                .await?;
-        }
-        for player in player_data.all_players() {
-            player
-                .send(PlayerMessage::GameEnd {
-                    winner_name: winner_name.clone(),
-                })
-                .await?;
-        }
-        Ok(())
-    }
-
-    async fn clean_up(data: &mut GameData) {
+    fn clean_up(data: &mut GameData) {
         for player in data.all_players() {
-            player
-                .send(PlayerMessage::GameEnded)
-                .await
-                .expect("Should successfully send");
+            player.send(PlayerMessage::GameEnded);
         }
     }
 }
@@ -296,14 +456,18 @@ impl Game {
 struct GameData {
     players: BTreeMap<usize, (Player, Arc<str>)>,
     code: String,
+    teams: Option<Teams>,
+    config: GameConfig,
 }
 
 impl GameData {
     #[inline]
-    fn new(code: String) -> Self {
+    fn new(code: String, config: GameConfig) -> Self {
         Self {
             players: BTreeMap::new(),
             code,
+            teams: config.teams.map(|teams| Teams::new(teams.count)),
+            config,
         }
     }
 
@@ -332,66 +496,247 @@ impl GameData {
     }
 
     #[inline]
-    async fn send_player(&self, id: &usize, msg: PlayerMessage) -> Result<()> {
+    fn send_player(&self, id: &usize, msg: PlayerMessage) -> Result<()> {
         let player = self
             .get_player(id)
             .ok_or_else(|| anyhow!("Player not found"))?;
-        player.send(msg).await
+        player.send(msg);
+        Ok(())
     }
 
-    #[inline]
-    fn get_player_name(&self, id: &usize) -> Option<Arc<str>> {
-        self.players.get(id).map(|(_, name)| name).cloned()
+    /// Removes the players whose connection is gone without them quitting, returning their ids.
+    fn remove_closed(&mut self) -> Vec<usize> {
+        let closed = self
+            .all_players_and_ids()
+            .filter(|(_, player)| player.is_closed())
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        for id in &closed {
+            self.players.remove(id);
+            if let Some(teams) = &mut self.teams {
+                teams.leave(id);
+            }
+        }
+        closed
     }
-}
 
-struct InGameData {
-    player_state: BTreeMap<usize, PlayerState>,
-    stack: Stack,
-}
-
-impl InGameData {
-    #[inline]
-    fn new() -> Self {
-        Self {
-            player_state: BTreeMap::new(),
-            stack: Stack::new(10),
+    /// Relays a chat message to everyone in the room.
+    fn chat(&self, id: &usize, text: Arc<str>) {
+        let Some(player_name) = self.get_player_name(id) else {
+            return;
+        };
+        for player in self.all_players() {
+            player.send(PlayerMessage::Chat {
+                player_name: player_name.clone(),
+                text: text.clone(),
+            });
         }
     }
 
-    #[inline]
-    fn game_ended(&self) -> bool {
-        self.player_state.len() <= 1
+    /// Tells everyone who is on which team, in team mode.
+    fn send_teams(&self) {
+        let Some(teams) = &self.teams else {
+            return;
+        };
+        let teams = (0..self.config.teams.map_or(0, |options| options.count))
+            .map(|team| {
+                teams
+                    .members(team)
+                    .filter_map(|id| self.get_player_name(&id))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        for player in self.all_players() {
+            player.send(PlayerMessage::Teams {
+                teams: teams.clone(),
+            });
+        }
     }
 
+    /// Replies to a rejected action; actions from unknown players are dropped.
     #[inline]
-    fn get_state(&self, id: &usize) -> Option<&PlayerState> {
-        self.player_state.get(id)
+    fn reject(
+        &self,
+        id: &usize,
+        action: &PlayerAction,
+        code: ErrorCode,
+        message: impl Into<Arc<str>>,
+    ) {
+        if let Some(player) = self.get_player(id) {
+            player.send(action.reject(code, message));
+        }
     }
 
     #[inline]
-    fn next_id(&self, id: usize) -> usize {
-        *self
-            .player_state
-            .range(id + 1..)
-            .next()
-            .or_else(|| self.player_state.first_key_value())
-            .unwrap()
-            .0
+    fn get_player_name(&self, id: &usize) -> Option<Arc<str>> {
+        self.players.get(id).map(|(_, name)| name).cloned()
     }
 
-    #[inline]
-    async fn gen_cards(&mut self) -> Vec<Card> {
-        static CARD_DISTRIBUTION: LazyLock<CardDistribution> = LazyLock::new(CardDistribution::default);
-
-        let mut rng = RNG.lock().await;
-        (0..3)
-            .map(|_| rng.sample(LazyLock::force(&CARD_DISTRIBUTION)))
-            .collect()
+    /// Tells the players about what happened in the game.
+    fn deliver(&self, events: Vec<Event>) {
+        let send = |id: &usize, msg: PlayerMessage| {
+            if let Some(player) = self.get_player(id) {
+                player.send(msg);
+            }
+        };
+        for event in events {
+            match event {
+                Event::Started { seating, point } => {
+                    let seating = seating
+                        .iter()
+                        .filter_map(|id| self.get_player_name(id))
+                        .collect::<Vec<_>>();
+                    for player in self.all_players() {
+                        player.send(PlayerMessage::Start {
+                            point,
+                            seating: seating.clone(),
+                        });
+                    }
+                }
+                Event::Hand { id, cards } => send(&id, PlayerMessage::Hand { cards }),
+                Event::TurnStarted {
+                    id: playing_id,
+                    cards,
+                    stack,
+                    scores,
+                } => {
+                    let Some(player_name) = self.get_player_name(&playing_id) else {
+                        continue;
+                    };
+                    for (id, player) in self.all_players_and_ids() {
+                        player.send(PlayerMessage::Scoreboard {
+                            scores: scores.values().cloned().collect(),
+                        });
+                        player.send(PlayerMessage::RoundStart {
+                            player_name: player_name.clone(),
+                            stack: stack.clone(),
+                            point: scores.get(&id).and_then(|score| score.point),
+                        });
+                    }
+                    send(&playing_id, PlayerMessage::NewRound { cards, stack });
+                }
+                Event::PlayAgain {
+                    id,
+                    cards,
+                    stack,
+                    scores,
+                } => {
+                    for player in self.all_players() {
+                        player.send(PlayerMessage::Scoreboard {
+                            scores: scores.values().cloned().collect(),
+                        });
+                    }
+                    send(&id, PlayerMessage::NewRound { cards, stack });
+                }
+                Event::CardPlayed {
+                    id: playing_id,
+                    card,
+                    target,
+                    ..
+                } => {
+                    let target = target.and_then(|id| self.get_player_name(&id));
+                    for (id, player) in self.all_players_and_ids() {
+                        if playing_id != id {
+                            player.send(PlayerMessage::OtherUseCard {
+                                card: card.clone(),
+                                target: target.clone(),
+                            });
+                        }
+                    }
+                }
+                Event::Discarded {
+                    id: playing_id,
+                    card,
+                } => {
+                    for (id, player) in self.all_players_and_ids() {
+                        if playing_id != id {
+                            player.send(PlayerMessage::OtherDiscard { card: card.clone() });
+                        }
+                    }
+                }
+                Event::Passed { id: playing_id } => {
+                    for (id, player) in self.all_players_and_ids() {
+                        if playing_id != id {
+                            player.send(PlayerMessage::OtherPass);
+                        }
+                    }
+                }
+                Event::PowerUsed { id, power, target } => {
+                    let Some(player_name) = self.get_player_name(&id) else {
+                        continue;
+                    };
+                    let target = target.and_then(|id| self.get_player_name(&id));
+                    for player in self.all_players() {
+                        player.send(PlayerMessage::PowerUsed {
+                            player_name: player_name.clone(),
+                            power,
+                            target: target.clone(),
+                        });
+                    }
+                }
+                Event::Peeked { id, of, cards } => {
+                    if let Some(player_name) = self.get_player_name(&of) {
+                        send(&id, PlayerMessage::Peek { player_name, cards });
+                    }
+                }
+                Event::Previewed {
+                    id,
+                    stack_after,
+                    overflows,
+                    changes,
+                } => {
+                    let point_changes = changes
+                        .into_iter()
+                        .filter_map(|(id, change)| {
+                            Some(PointChange {
+                                name: self.get_player_name(&id)?,
+                                change,
+                            })
+                        })
+                        .collect();
+                    send(
+                        &id,
+                        PlayerMessage::PreviewResult {
+                            stack_after,
+                            overflows,
+                            point_changes,
+                        },
+                    );
+                }
+                Event::Lost { id } => send(&id, PlayerMessage::Lose),
+                Event::Rejected {
+                    id,
+                    action,
+                    code,
+                    message,
+                } => send(
+                    &id,
+                    PlayerMessage::error(code, message, Some(action.into())),
+                ),
+                Event::Ended {
+                    winners,
+                    winning_teams,
+                } => {
+                    let winner_names = winners
+                        .iter()
+                        .filter_map(|id| {
+                            send(id, PlayerMessage::Win);
+                            self.get_player_name(id)
+                        })
+                        .collect::<Vec<_>>();
+                    let winner_name = match winner_names.as_slice() {
+                        [winner_name] => Some(winner_name.clone()),
+                        _ => None,
+                    };
+                    for player in self.all_players() {
+                        player.send(PlayerMessage::GameEnd {
+                            winner_name: winner_name.clone(),
+                            winner_names: winner_names.clone(),
+                            winning_teams: winning_teams.clone(),
+                        });
+                    }
+                }
+            }
+        }
     }
 }
-
-#[derive(Default, PartialEq, Eq, Debug)]
-struct PlayerState {
-    point: i32,
-}
@@ -0,0 +1,19 @@
+pub mod assets;
+pub mod bot;
+pub mod card_set;
+#[cfg(feature = "clients")]
+pub mod client;
+pub mod deck;
+pub mod game;
+pub mod outbox;
+pub mod player;
+pub mod protocol;
+pub mod room_code;
+pub mod routes;
+pub mod scoring;
+pub mod server;
+pub mod simulation;
+pub mod stack;
+pub mod state;
+pub mod team;
+pub mod transport;
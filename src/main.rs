@@ -1,71 +1,46 @@
+//! The game server: the HTTP and WebSocket endpoints on port 8080, TCP players on
+//! `TCP_ADDR`, and the frontend from `STATIC_DIR`, `static` by default.
+//!
+//! The frontend is built into `static` from `frontend` with `npm ci && npm run build`, which
+//! compiles the LESS styles with `lessc`. `ALLOWED_ORIGINS`, comma separated, lets pages on
+//! other hosts call the API; without it only the bundled frontend can.
+
 use std::{
-    convert::Infallible,
     net::{Ipv4Addr, SocketAddrV4},
+    path::Path,
 };
 
-use serde_json::json;
-use server::Server;
-use warp::{filters::ws::Ws, Filter};
-
-pub mod game;
-pub mod player;
-pub mod server;
-pub mod stack;
+use chatroom_rust::{card_set::CardSets, room_code::RoomCodes, routes, server::Server};
+use tokio::net::TcpListener;
 
 #[tokio::main]
 async fn main() {
-    let server = Server::default();
-    let cors = warp::cors::cors()
-        .allow_any_origin()
-        .build();
-    let create_game = warp::path("create-game")
-        .and(warp::path::end())
-        .and(warp::post())
-        .and_then({
-            let server = server.clone();
-            move || {
-                let server = server.clone();
-                async move {
-                    let game_code = server.new_game().await;
-                    Ok::<_, Infallible>(json!({ "game_code": game_code }).to_string())
-                }
-            }
-        })
-        .with(&cors);
-
-    let game_exist = warp::path("game-exist")
-        .and(warp::path::param())
-        .and(warp::path::end())
-        .and(warp::get())
-        .and_then({
-            let server = server.clone();
-            move |game_code: String| {
-                let server = server.clone();
-                async move {
-                    let is_game_exist = server.is_game_exist(&game_code).await;
-                    Ok::<_, Infallible>(json!({ "game_exist": is_game_exist }).to_string())
-                }
-            }
-        })
-        .with(&cors);
+    let card_sets_dir = std::env::var("CARD_SETS_DIR").unwrap_or_else(|_| "card_sets".to_string());
+    let card_sets = CardSets::load(Path::new(&card_sets_dir)).expect("Should load card sets");
+    let room_codes = match std::env::var("ROOM_CODES") {
+        Ok(room_codes) => room_codes.parse().expect("Should be valid room codes"),
+        Err(_) => RoomCodes::default(),
+    };
+    let server = Server::new(card_sets).with_room_codes(room_codes);
+    let static_dir = std::env::var("STATIC_DIR").unwrap_or_else(|_| "static".to_string());
+    let allowed_origins = std::env::var("ALLOWED_ORIGINS").ok();
+    let tcp_addr = std::env::var("TCP_ADDR").unwrap_or_else(|_| "127.0.0.1:8081".to_string());
 
-    let join_game = warp::path("game")
-        .and(warp::path::param())
-        .and(warp::ws())
-        .map({
-            let server = server.clone();
-            move |game_code: String, ws: Ws| {
-                let server = server.clone();
-                ws.on_upgrade(|socket| async move {
-                    let _ = server
-                        .add_player_to_game(socket, &game_code)
-                        .await;
-                })
-            }
-        })
-        .with(&cors);
+    match TcpListener::bind(&tcp_addr).await {
+        Ok(tcp_listener) => {
+            tokio::spawn(server.clone().accept_tcp_players(tcp_listener));
+        }
+        Err(err) => eprintln!("ERROR: Could not listen for TCP players on {tcp_addr}: {err}"),
+    }
 
-    warp::serve(create_game.or(game_exist).or(join_game))
+    let routes = match routes::routes(server, static_dir, allowed_origins.as_deref()) {
+        Ok(routes) => routes,
+        Err(err) => {
+            eprintln!("ERROR: {err}");
+            std::process::exit(1);
+        }
+    };
+    warp::serve(routes)
         .run(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 8080))
         .await;
 }
@@ -0,0 +1,253 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::Notify;
+
+use crate::player::PlayerMessage;
+
+/// How many messages may wait for one player's socket before the overflow policy kicks in.
+pub const OUTBOX_CAPACITY: usize = 32;
+
+/// How a message is treated when the player's queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// Can be lost without breaking the client; the oldest queued one is dropped first.
+    Cosmetic,
+    /// Must reach the client; if there is no room for it the player is disconnected.
+    Critical,
+}
+
+/// The queue was full of critical messages and the player has to be disconnected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overflowed;
+
+#[derive(Debug)]
+struct State {
+    messages: VecDeque<PlayerMessage>,
+    capacity: usize,
+    senders: usize,
+    receiver_alive: bool,
+    overflowed: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    notify: Notify,
+}
+
+/// Creates a bounded outbound queue for one player.
+///
+/// Unlike an mpsc channel, pushing never waits: when the queue is full the overflow policy of
+/// [`Delivery`] is applied instead, so the game task is never blocked by a slow client.
+pub fn channel(capacity: usize) -> (OutboxSender, OutboxReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            messages: VecDeque::with_capacity(capacity),
+            capacity,
+            senders: 1,
+            receiver_alive: true,
+            overflowed: false,
+        }),
+        notify: Notify::new(),
+    });
+    (
+        OutboxSender {
+            shared: shared.clone(),
+        },
+        OutboxReceiver { shared },
+    )
+}
+
+#[derive(Debug)]
+pub struct OutboxSender {
+    shared: Arc<Shared>,
+}
+
+impl OutboxSender {
+    pub fn push(&self, msg: PlayerMessage) {
+        let mut state = self.shared.state.lock().expect("Should not be poisoned");
+        if !state.receiver_alive || state.overflowed {
+            return;
+        }
+        if state.messages.len() >= state.capacity {
+            let oldest_cosmetic = state
+                .messages
+                .iter()
+                .position(|queued| queued.delivery() == Delivery::Cosmetic);
+            match (oldest_cosmetic, msg.delivery()) {
+                (Some(index), _) => {
+                    state.messages.remove(index);
+                }
+                (None, Delivery::Cosmetic) => return,
+                (None, Delivery::Critical) => {
+                    state.overflowed = true;
+                    state.messages.clear();
+                    drop(state);
+                    self.shared.notify.notify_one();
+                    return;
+                }
+            }
+        }
+        state.messages.push_back(msg);
+        drop(state);
+        self.shared.notify.notify_one();
+    }
+
+    /// Whether the player's connection is gone, so that nothing pushed will reach it.
+    pub fn is_closed(&self) -> bool {
+        let state = self.shared.state.lock().expect("Should not be poisoned");
+        !state.receiver_alive || state.overflowed
+    }
+}
+
+impl Clone for OutboxSender {
+    fn clone(&self) -> Self {
+        self.shared
+            .state
+            .lock()
+            .expect("Should not be poisoned")
+            .senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for OutboxSender {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().expect("Should not be poisoned");
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            self.shared.notify.notify_one();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct OutboxReceiver {
+    shared: Arc<Shared>,
+}
+
+impl OutboxReceiver {
+    /// Waits for the next message.
+    ///
+    /// Returns `Some(Err(Overflowed))` once if the queue overflowed, and `None` when every
+    /// sender is gone and the queue is drained.
+    pub async fn recv(&mut self) -> Option<Result<PlayerMessage, Overflowed>> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().expect("Should not be poisoned");
+                if state.overflowed {
+                    return if state.receiver_alive {
+                        state.receiver_alive = false;
+                        Some(Err(Overflowed))
+                    } else {
+                        None
+                    };
+                }
+                if let Some(msg) = state.messages.pop_front() {
+                    return Some(Ok(msg));
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+}
+
+impl Drop for OutboxReceiver {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().expect("Should not be poisoned");
+        state.receiver_alive = false;
+        state.messages.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stack::Stack;
+
+    use super::*;
+
+    fn chat(text: &str) -> PlayerMessage {
+        PlayerMessage::Chat {
+            player_name: "p1".into(),
+            text: text.into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn full_queue_drops_the_oldest_cosmetic_message() {
+        let (sender, mut receiver) = channel(3);
+        sender.push(PlayerMessage::Lose);
+        sender.push(chat("first"));
+        sender.push(chat("second"));
+        sender.push(PlayerMessage::Win);
+        drop(sender);
+        assert_eq!(receiver.recv().await, Some(Ok(PlayerMessage::Lose)));
+        assert_eq!(receiver.recv().await, Some(Ok(chat("second"))));
+        assert_eq!(receiver.recv().await, Some(Ok(PlayerMessage::Win)));
+        assert_eq!(receiver.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn cosmetic_message_is_dropped_when_only_critical_ones_are_queued() {
+        let (sender, mut receiver) = channel(2);
+        sender.push(PlayerMessage::Lose);
+        sender.push(PlayerMessage::Win);
+        sender.push(chat("dropped"));
+        drop(sender);
+        assert_eq!(receiver.recv().await, Some(Ok(PlayerMessage::Lose)));
+        assert_eq!(receiver.recv().await, Some(Ok(PlayerMessage::Win)));
+        assert_eq!(receiver.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn previews_and_scoreboards_are_not_dropped() {
+        let preview = || PlayerMessage::PreviewResult {
+            stack_after: Stack::default(),
+            overflows: Vec::new(),
+            point_changes: Vec::new(),
+        };
+        let scoreboard = || PlayerMessage::Scoreboard { scores: Vec::new() };
+        let (sender, mut receiver) = channel(2);
+        sender.push(preview());
+        sender.push(scoreboard());
+        sender.push(chat("dropped"));
+        drop(sender);
+        assert_eq!(receiver.recv().await, Some(Ok(preview())));
+        assert_eq!(receiver.recv().await, Some(Ok(scoreboard())));
+        assert_eq!(receiver.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn overflow_is_reported_once() {
+        let (sender, mut receiver) = channel(1);
+        sender.push(PlayerMessage::Lose);
+        sender.push(PlayerMessage::Win);
+        sender.push(PlayerMessage::GameEnded);
+        assert_eq!(receiver.recv().await, Some(Err(Overflowed)));
+        assert_eq!(receiver.recv().await, None);
+        assert_eq!(receiver.recv().await, None);
+    }
+
+    #[test]
+    fn pushes_after_the_receiver_is_dropped_are_ignored() {
+        let (sender, receiver) = channel(1);
+        assert!(!sender.is_closed());
+        drop(receiver);
+        assert!(sender.is_closed());
+        for _ in 0..3 {
+            sender.push(PlayerMessage::Win);
+        }
+        let state = sender.shared.state.lock().unwrap();
+        assert!(state.messages.is_empty());
+        assert!(!state.overflowed);
+    }
+}
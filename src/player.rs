@@ -1,19 +1,20 @@
 use std::sync::Arc;
 
-use anyhow::Result;
 use futures::{stream_select, SinkExt, StreamExt as _};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc::{Receiver as MpscReceiver, Sender as MpscSender};
-use tokio_stream::wrappers::ReceiverStream;
-use warp::filters::ws::{self, WebSocket};
+use tokio::sync::mpsc::Sender as MpscSender;
 
-use crate::stack::{Card, Stack};
+use crate::{
+    outbox::{Delivery, OutboxReceiver, OutboxSender, Overflowed},
+    protocol::{self, Encoding, Feature, Frame, Rejection},
+    stack::{Card, Overflow, Power, Stack},
+    transport::{self, Connection, Packet},
+};
 
-#[derive(Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum PlayerMessage {
-    #[serde(skip)]
-    Register(usize),
     NewPlayer {
         name: Arc<str>,
     },
@@ -25,6 +26,9 @@ pub enum PlayerMessage {
     GameStarted,
     Start {
         point: i32,
+        /// Player names in turn order, play starts with the first one.
+        #[serde(default)]
+        seating: Vec<Arc<str>>,
     },
     StartFailed,
     RoundStart {
@@ -34,22 +38,155 @@ pub enum PlayerMessage {
     },
     OtherUseCard {
         card: Card,
+        /// The opponent who absorbs the overflows, in a targeted game.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target: Option<Arc<str>>,
+    },
+    /// Another player paid the discard cost to throw `card` away.
+    OtherDiscard {
+        card: Card,
     },
+    /// Another player ended their turn without playing the cards they had left.
+    OtherPass,
     NewRound {
         cards: Vec<Card>,
         stack: Stack,
     },
+    /// What playing a card would do, in answer to [`PlayerAction::Preview`].
+    PreviewResult {
+        stack_after: Stack,
+        overflows: Vec<Overflow>,
+        point_changes: Vec<PointChange>,
+    },
+    /// A power card took effect; `target` is the player it was used on, if any.
+    PowerUsed {
+        player_name: Arc<str>,
+        power: Power,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target: Option<Arc<str>>,
+    },
+    /// The cards another player will get, sent only to whoever played a peek card.
+    Peek {
+        player_name: Arc<str>,
+        cards: Vec<Card>,
+    },
+    /// Names of the players on every team, sent in team mode whenever it changes.
+    Teams {
+        teams: Vec<Vec<Arc<str>>>,
+    },
+    /// The player's own hand in deck mode, sent whenever it changes.
+    Hand {
+        cards: Vec<Card>,
+    },
     Lose,
     GameEnd {
+        /// The winner when there is exactly one, kept for clients that predate ties.
         winner_name: Option<Arc<str>>,
+        /// Everyone who won; several players on a tie or in team mode.
+        #[serde(default)]
+        winner_names: Vec<Arc<str>>,
+        /// The winning teams in team mode, several on a tie.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        winning_teams: Vec<usize>,
     },
     Win,
-    InvalidOperation,
+    Chat {
+        player_name: Arc<str>,
+        text: Arc<str>,
+    },
+    Scoreboard {
+        scores: Vec<Score>,
+    },
+    Welcome {
+        protocol_version: u32,
+        min_protocol_version: u32,
+        features: Vec<Feature>,
+        encoding: Encoding,
+    },
+    Error {
+        code: ErrorCode,
+        message: Arc<str>,
+        offending_type: Option<Arc<str>>,
+    },
+}
+
+/// One line of the scoreboard; `point` is `None` once the player has lost, `hand_size` is
+/// only sent in deck mode.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct Score {
+    pub name: Arc<str>,
+    pub point: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hand_size: Option<usize>,
+    /// Only sent in team mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<usize>,
+}
+
+/// Points a player would gain, or lose when negative, in a [`PlayerMessage::PreviewResult`].
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct PointChange {
+    pub name: Arc<str>,
+    pub change: i32,
+}
+
+/// Longest chat message accepted, in characters.
+pub const MAX_CHAT_LEN: usize = 500;
+
+/// Why an action from the client was rejected.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The frame was not a text frame.
+    UnsupportedFrame,
+    /// The frame could not be parsed into a known action.
+    MalformedAction,
+    /// The action needs the player to join the game first.
+    NotJoined,
+    /// The player tried to join twice.
+    AlreadyJoined,
+    /// The name to join with is empty or another player already has it.
+    InvalidName,
+    /// The action is only allowed for the playing player.
+    NotYourTurn,
+    /// `card_index` does not point to a card the player holds.
+    InvalidCardIndex,
+    /// The action is not allowed at this point of the game.
+    IllegalInPhase,
+    /// `target` is missing, unknown, or not an opponent.
+    InvalidTarget,
+}
+
+impl PlayerMessage {
+    pub fn error(
+        code: ErrorCode,
+        message: impl Into<Arc<str>>,
+        offending_type: Option<Arc<str>>,
+    ) -> Self {
+        PlayerMessage::Error {
+            code,
+            message: message.into(),
+            offending_type,
+        }
+    }
+
+    pub fn delivery(&self) -> Delivery {
+        match self {
+            PlayerMessage::NewPlayer { .. }
+            | PlayerMessage::OtherUseCard { .. }
+            | PlayerMessage::OtherDiscard { .. }
+            | PlayerMessage::OtherPass
+            | PlayerMessage::Chat { .. } => Delivery::Cosmetic,
+            // A preview is answered only once, and the scoreboard alone carries hand sizes.
+            _ => Delivery::Critical,
+        }
+    }
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum PlayerAction {
+    /// The connection failed; the game treats it like [`PlayerAction::Quit`].
     #[serde(skip)]
     Error(anyhow::Error),
     #[serde(skip)]
@@ -57,113 +194,279 @@ pub enum PlayerAction {
         player: Player,
         name: Arc<str>,
     },
+    Hello {
+        protocol_version: u32,
+        client_name: Arc<str>,
+        /// Unknown capabilities are accepted and ignored.
+        #[serde(default)]
+        #[schemars(with = "Vec<String>")]
+        capabilities: Vec<Feature>,
+    },
     Join {
         name: Arc<str>,
     },
     Start,
     UseCard {
         card_index: usize,
+        /// Name of the opponent who absorbs the overflows, required in a targeted game.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target: Option<Arc<str>>,
+    },
+    /// Throws a card away for a point cost instead of playing it.
+    Discard {
+        card_index: usize,
+    },
+    /// Ends the turn without playing any more cards.
+    Pass,
+    /// Moves to another team before the game starts, in team mode.
+    PickTeam {
+        team: usize,
+    },
+    /// Asks what playing a card would do, without playing it.
+    Preview {
+        card_index: usize,
+        /// Without a target, a targeted game is previewed as if every opponent was hit.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target: Option<Arc<str>>,
+    },
+    Chat {
+        text: Arc<str>,
     },
     Quit,
 }
 
+impl PlayerAction {
+    /// The opponent named by a `use_card` or `preview`.
+    pub fn target(&self) -> Option<&Arc<str>> {
+        match self {
+            PlayerAction::UseCard { target, .. } | PlayerAction::Preview { target, .. } => {
+                target.as_ref()
+            }
+            _ => None,
+        }
+    }
+
+    /// The `type` tag this action is sent with.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            PlayerAction::Error(_) => "error",
+            PlayerAction::Hello { .. } => "hello",
+            PlayerAction::JoinWithPlayer { .. } | PlayerAction::Join { .. } => "join",
+            PlayerAction::Start => "start",
+            PlayerAction::UseCard { .. } => "use_card",
+            PlayerAction::Discard { .. } => "discard",
+            PlayerAction::Pass => "pass",
+            PlayerAction::Preview { .. } => "preview",
+            PlayerAction::PickTeam { .. } => "pick_team",
+            PlayerAction::Chat { .. } => "chat",
+            PlayerAction::Quit => "quit",
+        }
+    }
+
+    /// Builds the error reply for this action.
+    pub fn reject(&self, code: ErrorCode, message: impl Into<Arc<str>>) -> PlayerMessage {
+        PlayerMessage::error(code, message, Some(self.type_name().into()))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Player {
-    message_sender: MpscSender<PlayerMessage>,
+    message_sender: OutboxSender,
 }
 
 impl Player {
-    pub fn new(message_sender: MpscSender<PlayerMessage>) -> Self {
-        Self {
-            message_sender,
-        }
+    pub fn new(message_sender: OutboxSender) -> Self {
+        Self { message_sender }
     }
 
-    pub async fn send(&self, msg: PlayerMessage) -> Result<()> {
-        Ok(self.message_sender.send(msg).await?)
+    /// Queues a message for this player without waiting for the socket.
+    pub fn send(&self, msg: PlayerMessage) {
+        self.message_sender.push(msg);
+    }
+
+    /// Whether the connection is gone; the game removes such a player.
+    pub fn is_closed(&self) -> bool {
+        self.message_sender.is_closed()
     }
 }
 
 enum Message {
-    Frontend(ws::Message),
+    Frontend(Packet),
     Backend(PlayerMessage),
+    Overflowed,
 }
 
 pub async fn handle_one_player(
+    id: usize,
     player: Player,
-    ws: WebSocket,
+    connection: Connection,
     action_sender: MpscSender<(PlayerAction, usize)>,
-    message_receiver: MpscReceiver<PlayerMessage>,
+    message_receiver: OutboxReceiver,
 ) {
-    let mut id = None;
-    let message_stream = ReceiverStream::new(message_receiver).map(|msg| Ok(Message::Backend(msg)));
-    let (mut ws_sender, ws_recv_stream) = ws.split();
-    let ws_recv_stream = ws_recv_stream.map(|msg| msg.map(|msg| Message::Frontend(msg)));
-    let mut message_stream = stream_select!(ws_recv_stream, message_stream);
+    let mut greeted = false;
+    let mut encoding = Encoding::default();
+    let mut joined = false;
+    let message_stream = futures::stream::unfold(message_receiver, |mut receiver| async move {
+        let msg = match receiver.recv().await? {
+            Ok(msg) => Message::Backend(msg),
+            Err(Overflowed) => Message::Overflowed,
+        };
+        Some((Ok(msg), receiver))
+    })
+    .boxed();
+    let Connection {
+        mut sink,
+        stream,
+        binary,
+    } = connection;
+    let frontend_stream = stream.map(|packet| packet.map(Message::Frontend));
+    let mut message_stream = stream_select!(frontend_stream, message_stream);
     while let Some(message) = message_stream.next().await {
+        // The game may already be over when the player leaves, nobody is left to tell then.
         if let Err(err) = message {
-            action_sender
-                .send((PlayerAction::Error(err.into()), id.unwrap_or(0)))
-                .await
-                .expect("Should successfully send");
+            let _ = action_sender.send((PlayerAction::Error(err), id)).await;
             break;
         }
         match message.unwrap() {
-            Message::Backend(PlayerMessage::Register(reg_id)) => {
-                id = Some(reg_id);
+            Message::Overflowed => {
+                if let Err(err) =
+                    transport::close(&mut sink, 1008u16, "Too many pending messages").await
+                {
+                    eprintln!("ERROR: {err}");
+                }
+                quit(&action_sender, id).await;
+                break;
             }
             Message::Backend(msg) => {
-                ws_sender
-                    .send(ws::Message::text(
-                        serde_json::to_string(&msg).expect("Should successfully serialize"),
-                    ))
-                    .await
-                    .expect("Should successfully send");
-                if msg == PlayerMessage::GameStarted{
-                    ws_sender
-                        .send(ws::Message::close_with(1000u16, "Game started"))
-                        .await
-                        .expect("Should successfully close");
-                    ws_sender.close().await.expect("Should successfully close");
-                    break;
+                // The game turned the name down, another one may be tried.
+                let join_failed = matches!(
+                    &msg,
+                    PlayerMessage::Error { offending_type: Some(action), .. } if &**action == "join"
+                );
+                if join_failed {
+                    joined = false;
                 }
-                if msg == PlayerMessage::GameEnded {
-                    ws_sender
-                        .send(ws::Message::close_with(1000u16, "Game ended"))
-                        .await
-                        .expect("Should successfully close");
-                    ws_sender.close().await.expect("Should successfully close");
+                let closing = match msg {
+                    PlayerMessage::GameStarted => Some("Game started"),
+                    PlayerMessage::GameEnded => Some("Game ended"),
+                    _ => None,
+                };
+                let mut sent = sink.send(Packet::Frame(encoding.encode(&msg))).await;
+                if let (Ok(()), Some(reason)) = (&sent, closing) {
+                    sent = transport::close(&mut sink, 1000u16, reason).await;
+                }
+                if let Err(err) = sent {
+                    eprintln!("ERROR: {err}");
+                    quit(&action_sender, id).await;
                     break;
                 }
-            }
-            Message::Frontend(msg) => {
-                if msg.is_close() {
-                    println!("{:?}", msg.close_frame());
-                    action_sender
-                        .send((PlayerAction::Quit, id.unwrap_or(0)))
-                        .await
-                        .expect("Should successfully send");
+                if closing.is_some() {
                     break;
                 }
-                let msg = msg.to_str();
-                if let Err(_) = msg {
-                    continue;
+            }
+            Message::Frontend(Packet::Close(_)) => {
+                quit(&action_sender, id).await;
+                break;
+            }
+            Message::Frontend(Packet::Frame(frame)) => {
+                if !greeted {
+                    let hello = match &frame {
+                        Frame::Text(text) => serde_json::from_str::<PlayerAction>(text).ok(),
+                        Frame::Binary(_) => None,
+                    };
+                    let negotiated = match hello {
+                        Some(PlayerAction::Hello {
+                            protocol_version,
+                            mut capabilities,
+                            ..
+                        }) => {
+                            if !binary {
+                                capabilities.retain(|feature| *feature != Feature::Msgpack);
+                            }
+                            protocol::negotiate(protocol_version, &capabilities)
+                        }
+                        _ => Err(Rejection::expected_hello()),
+                    };
+                    match negotiated {
+                        Ok(negotiated) => {
+                            greeted = true;
+                            encoding = negotiated;
+                            let welcome = Packet::Frame(
+                                Encoding::Json.encode(&protocol::welcome(encoding, binary)),
+                            );
+                            if let Err(err) = sink.send(welcome).await {
+                                eprintln!("ERROR: {err}");
+                                quit(&action_sender, id).await;
+                                break;
+                            }
+                            continue;
+                        }
+                        Err(rejection) => {
+                            if let Err(err) =
+                                transport::close(&mut sink, rejection.code, rejection.reason).await
+                            {
+                                eprintln!("ERROR: {err}");
+                                quit(&action_sender, id).await;
+                            }
+                            break;
+                        }
+                    }
                 }
-                let msg = msg.unwrap();
 
-                let player_action = serde_json::from_str(&msg);
-                if let Err(_) = player_action {
-                    continue;
+                let mut player_action = match encoding.decode(&frame) {
+                    Ok(player_action) => player_action,
+                    Err((code, message, offending_type)) => {
+                        player.send(PlayerMessage::error(code, message, offending_type));
+                        continue;
+                    }
+                };
+                match player_action {
+                    PlayerAction::Hello { .. } => {
+                        player.send(
+                            player_action
+                                .reject(ErrorCode::IllegalInPhase, "Handshake already done"),
+                        );
+                        continue;
+                    }
+                    PlayerAction::Join { .. } if joined => {
+                        player.send(
+                            player_action
+                                .reject(ErrorCode::AlreadyJoined, "Already joined this game"),
+                        );
+                        continue;
+                    }
+                    PlayerAction::Join { name } => {
+                        joined = true;
+                        player_action = PlayerAction::JoinWithPlayer {
+                            player: player.clone(),
+                            name,
+                        };
+                    }
+                    PlayerAction::Quit => (),
+                    _ if !joined => {
+                        player.send(
+                            player_action.reject(ErrorCode::NotJoined, "Join the game first"),
+                        );
+                        continue;
+                    }
+                    PlayerAction::Chat { ref text } if text.chars().count() > MAX_CHAT_LEN => {
+                        player.send(player_action.reject(
+                            ErrorCode::MalformedAction,
+                            format!("Chat messages are limited to {MAX_CHAT_LEN} characters"),
+                        ));
+                        continue;
+                    }
+                    _ => (),
                 }
-                let mut player_action = player_action.unwrap();
-                if let PlayerAction::Join { name } = player_action {
-                    player_action = PlayerAction::JoinWithPlayer { player: player.clone(), name };
+                if action_sender.send((player_action, id)).await.is_err() {
+                    break;
                 }
-                action_sender
-                    .send((player_action, id.unwrap_or(0)))
-                    .await
-                    .expect("Should successfully send");
             }
         }
     }
 }
+
+/// Tells the game that the player left; it may already be over, with nobody left to tell.
+async fn quit(action_sender: &MpscSender<(PlayerAction, usize)>, id: usize) {
+    let _ = action_sender.send((PlayerAction::Quit, id)).await;
+}
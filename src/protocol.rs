@@ -0,0 +1,440 @@
+use std::sync::Arc;
+
+use schemars::{gen::SchemaSettings, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    player::{ErrorCode, PlayerAction, PlayerMessage},
+    stack::{Action, Card, Stack},
+};
+
+/// Version of the game protocol this server speaks.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest client protocol version still accepted.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Optional parts of the protocol a client or the server may support.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    /// Rejected actions are answered with `PlayerMessage::Error`.
+    TypedErrors,
+    /// Frames after the handshake may be MessagePack encoded binary frames.
+    Msgpack,
+    /// Players can send `chat` actions, relayed to the room as `chat` messages.
+    Chat,
+    /// Any capability this server does not know about.
+    #[serde(other)]
+    #[schemars(skip)]
+    Unknown,
+}
+
+/// Features this server supports, sent to every client in `Welcome`.
+pub const FEATURES: &[Feature] = &[Feature::TypedErrors, Feature::Msgpack, Feature::Chat];
+
+/// Close code sent when the first frame is not a `hello`.
+pub const CLOSE_EXPECTED_HELLO: u16 = 4000;
+/// Close code sent when the client's protocol version is not supported.
+pub const CLOSE_UNSUPPORTED_VERSION: u16 = 4001;
+
+/// Why a client was refused during the handshake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub code: u16,
+    pub reason: String,
+}
+
+impl Rejection {
+    pub fn expected_hello() -> Self {
+        Self {
+            code: CLOSE_EXPECTED_HELLO,
+            reason: "Expected hello as the first frame".to_string(),
+        }
+    }
+}
+
+/// How frames are encoded after the handshake.
+///
+/// The `hello` and `welcome` frames are always JSON text frames, and JSON text frames are
+/// still accepted from clients that negotiated MessagePack.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    #[default]
+    Json,
+    Msgpack,
+}
+
+/// A frame as it goes over the wire, independent of the transport.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl Encoding {
+    pub fn encode(self, msg: &PlayerMessage) -> Frame {
+        match self {
+            Encoding::Json => {
+                Frame::Text(serde_json::to_string(msg).expect("Should successfully serialize"))
+            }
+            Encoding::Msgpack => {
+                Frame::Binary(rmp_serde::to_vec_named(msg).expect("Should successfully serialize"))
+            }
+        }
+    }
+
+    /// Decodes a client frame, returning the error and the frame's `type` tag if it has one.
+    pub fn decode(
+        self,
+        frame: &Frame,
+    ) -> Result<PlayerAction, (ErrorCode, String, Option<Arc<str>>)> {
+        match (self, frame) {
+            (_, Frame::Text(text)) => serde_json::from_str(text).map_err(|err| {
+                let offending_type = serde_json::from_str::<serde_json::Value>(text).ok();
+                (
+                    ErrorCode::MalformedAction,
+                    err.to_string(),
+                    type_tag(offending_type),
+                )
+            }),
+            (Encoding::Msgpack, Frame::Binary(bytes)) => {
+                rmp_serde::from_slice(bytes).map_err(|err| {
+                    let offending_type = rmp_serde::from_slice::<serde_json::Value>(bytes).ok();
+                    (
+                        ErrorCode::MalformedAction,
+                        err.to_string(),
+                        type_tag(offending_type),
+                    )
+                })
+            }
+            (Encoding::Json, Frame::Binary(_)) => Err((
+                ErrorCode::UnsupportedFrame,
+                "Binary frames need the msgpack capability".to_string(),
+                None,
+            )),
+        }
+    }
+}
+
+fn type_tag(value: Option<serde_json::Value>) -> Option<Arc<str>> {
+    value?.get("type")?.as_str().map(Into::into)
+}
+
+/// Checks the client's `hello` and picks the encoding for the rest of the session.
+pub fn negotiate(protocol_version: u32, capabilities: &[Feature]) -> Result<Encoding, Rejection> {
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&protocol_version) {
+        return Err(Rejection {
+            code: CLOSE_UNSUPPORTED_VERSION,
+            reason: format!(
+                "Unsupported protocol version {protocol_version}, server supports {MIN_PROTOCOL_VERSION} to {PROTOCOL_VERSION}"
+            ),
+        });
+    }
+    if capabilities.contains(&Feature::Msgpack) {
+        Ok(Encoding::Msgpack)
+    } else {
+        Ok(Encoding::Json)
+    }
+}
+
+/// The reply to a successful `hello`; MessagePack is only offered if the transport can carry
+/// binary frames.
+pub fn welcome(encoding: Encoding, binary: bool) -> PlayerMessage {
+    PlayerMessage::Welcome {
+        protocol_version: PROTOCOL_VERSION,
+        min_protocol_version: MIN_PROTOCOL_VERSION,
+        features: FEATURES
+            .iter()
+            .copied()
+            .filter(|feature| binary || *feature != Feature::Msgpack)
+            .collect(),
+        encoding,
+    }
+}
+
+/// JSON Schema of every frame in the protocol, served at `GET /protocol/schema`.
+///
+/// Frames sent by the server are `PlayerMessage`s and frames sent by clients are
+/// `PlayerAction`s; both are listed under `definitions` with the types they use.
+pub fn schema() -> serde_json::Value {
+    let mut generator = SchemaSettings::draft07().into_generator();
+    let player_message = generator.subschema_for::<PlayerMessage>();
+    let player_action = generator.subschema_for::<PlayerAction>();
+    generator.subschema_for::<Card>();
+    generator.subschema_for::<Action>();
+    generator.subschema_for::<Stack>();
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "Game protocol",
+        "protocol_version": PROTOCOL_VERSION,
+        "anyOf": [player_message, player_action],
+        "definitions": generator.take_definitions(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        player::{PointChange, Score},
+        stack::{Overflow, Power},
+    };
+
+    /// A validator for `schema`, resolving its `$ref`s against the definitions of `root`.
+    fn validator(schema: &Value, root: &Value) -> jsonschema::Validator {
+        let mut schema = schema.clone();
+        schema["definitions"] = root["definitions"].clone();
+        jsonschema::draft7::new(&schema).expect("Should be a valid schema")
+    }
+
+    /// Asserts every value matches `name` and every `oneOf` branch of `name` is exercised.
+    fn assert_covers(name: &str, values: &[Value]) {
+        let root = schema();
+        let definition = &root["definitions"][name];
+        let whole = validator(definition, &root);
+        for value in values {
+            assert!(whole.is_valid(value), "{value} should match {name}");
+        }
+        for branch in definition["oneOf"].as_array().into_iter().flatten() {
+            let variant = validator(branch, &root);
+            assert!(
+                values.iter().any(|value| variant.is_valid(value)),
+                "no sample for {name} variant {branch}"
+            );
+        }
+    }
+
+    fn sample_stack() -> Stack {
+        let mut stack = Stack::new(10);
+        stack.push(3);
+        stack.push(-2);
+        stack
+    }
+
+    fn sample_card() -> Card {
+        Card {
+            actions: vec![
+                Action::Push(4),
+                Action::Pop,
+                Action::Reverse,
+                Action::Add(-2),
+                Action::Neg,
+                Action::Dup,
+                Action::Swap,
+                Action::Mul(3),
+                Action::Rotate(-1),
+                Action::Clear,
+                Action::Sort,
+            ],
+            power: Some(Power::Shield),
+        }
+    }
+
+    #[test]
+    fn player_messages_match_schema() {
+        let name: Arc<str> = "alice".into();
+        let messages = [
+            PlayerMessage::NewPlayer { name: name.clone() },
+            PlayerMessage::HostStart,
+            PlayerMessage::Joined {
+                players_name: vec![name.clone()],
+            },
+            PlayerMessage::GameEnded,
+            PlayerMessage::GameStarted,
+            PlayerMessage::Start {
+                point: 10,
+                seating: vec![name.clone(), "bob".into()],
+            },
+            PlayerMessage::StartFailed,
+            PlayerMessage::RoundStart {
+                player_name: name.clone(),
+                stack: sample_stack(),
+                point: None,
+            },
+            PlayerMessage::OtherUseCard {
+                card: sample_card(),
+                target: Some("bob".into()),
+            },
+            PlayerMessage::OtherDiscard {
+                card: sample_card(),
+            },
+            PlayerMessage::OtherPass,
+            PlayerMessage::NewRound {
+                cards: vec![sample_card()],
+                stack: Stack::default(),
+            },
+            PlayerMessage::PreviewResult {
+                stack_after: sample_stack(),
+                overflows: vec![Overflow {
+                    other_lost: 3,
+                    self_gain: -2,
+                }],
+                point_changes: vec![PointChange {
+                    name: name.clone(),
+                    change: -2,
+                }],
+            },
+            PlayerMessage::Hand {
+                cards: vec![sample_card()],
+            },
+            PlayerMessage::PowerUsed {
+                player_name: name.clone(),
+                power: Power::Steal,
+                target: Some("bob".into()),
+            },
+            PlayerMessage::Peek {
+                player_name: "bob".into(),
+                cards: vec![sample_card()],
+            },
+            PlayerMessage::Lose,
+            PlayerMessage::GameEnd {
+                winner_name: Some(name.clone()),
+                winner_names: vec![name.clone()],
+                winning_teams: vec![],
+            },
+            PlayerMessage::GameEnd {
+                winner_name: None,
+                winner_names: vec![name.clone(), "bob".into()],
+                winning_teams: vec![0, 1],
+            },
+            PlayerMessage::Teams {
+                teams: vec![vec![name.clone()], vec![]],
+            },
+            PlayerMessage::Win,
+            PlayerMessage::Chat {
+                player_name: name.clone(),
+                text: "hi".into(),
+            },
+            PlayerMessage::Scoreboard {
+                scores: vec![
+                    Score {
+                        name: name.clone(),
+                        point: Some(3),
+                        hand_size: Some(2),
+                        team: Some(0),
+                    },
+                    Score {
+                        name: "bob".into(),
+                        point: None,
+                        hand_size: None,
+                        team: None,
+                    },
+                ],
+            },
+            welcome(Encoding::Msgpack, true),
+            PlayerMessage::error(
+                ErrorCode::NotYourTurn,
+                "Not your turn",
+                Some("use_card".into()),
+            ),
+        ];
+        let values = messages
+            .iter()
+            .map(|msg| serde_json::to_value(msg).unwrap())
+            .collect::<Vec<_>>();
+        assert_covers("PlayerMessage", &values);
+        for (msg, value) in messages.iter().zip(values) {
+            assert_eq!(
+                &serde_json::from_value::<PlayerMessage>(value).unwrap(),
+                msg
+            );
+        }
+    }
+
+    #[test]
+    fn player_actions_match_schema() {
+        let actions = [
+            json!({ "type": "hello", "protocol_version": 1, "client_name": "bot", "capabilities": ["msgpack", "future_thing"] }),
+            json!({ "type": "join", "name": "alice" }),
+            json!({ "type": "start" }),
+            json!({ "type": "use_card", "card_index": 2 }),
+            json!({ "type": "discard", "card_index": 0 }),
+            json!({ "type": "pass" }),
+            json!({ "type": "pick_team", "team": 1 }),
+            json!({ "type": "preview", "card_index": 1 }),
+            json!({ "type": "use_card", "card_index": 0, "target": "bob" }),
+            json!({ "type": "chat", "text": "gg" }),
+            json!({ "type": "quit" }),
+        ];
+        assert_covers("PlayerAction", &actions);
+        for action in actions {
+            assert!(serde_json::from_value::<PlayerAction>(action).is_ok());
+        }
+    }
+
+    #[test]
+    fn invalid_actions_are_rejected_by_schema_and_serde() {
+        let root = schema();
+        let definition = validator(&root["definitions"]["PlayerAction"], &root);
+        for action in [
+            json!({ "type": "use_card" }),
+            json!({ "type": "use_card", "card_index": -1 }),
+            json!({ "type": "join", "name": 3 }),
+            json!({ "type": "register" }),
+        ] {
+            assert!(!definition.is_valid(&action), "{action} should not match");
+            assert!(serde_json::from_value::<PlayerAction>(action).is_err());
+        }
+    }
+
+    #[test]
+    fn stack_types_match_schema() {
+        let card = serde_json::to_value(sample_card()).unwrap();
+        let actions = card["actions"].as_array().unwrap().clone();
+        assert_covers("Action", &actions);
+        assert_covers("Card", &[card]);
+        assert_covers("Stack", &[serde_json::to_value(sample_stack()).unwrap()]);
+    }
+
+    #[test]
+    fn msgpack_round_trips() {
+        let msg = PlayerMessage::RoundStart {
+            player_name: "alice".into(),
+            stack: sample_stack(),
+            point: Some(7),
+        };
+        let Frame::Binary(bytes) = Encoding::Msgpack.encode(&msg) else {
+            panic!("msgpack should produce binary frames");
+        };
+        assert_eq!(rmp_serde::from_slice::<PlayerMessage>(&bytes).unwrap(), msg);
+
+        let action =
+            rmp_serde::to_vec_named(&json!({ "type": "use_card", "card_index": 1 })).unwrap();
+        assert!(matches!(
+            Encoding::Msgpack.decode(&Frame::Binary(action.clone())),
+            Ok(PlayerAction::UseCard {
+                card_index: 1,
+                target: None
+            })
+        ));
+        assert!(matches!(
+            Encoding::Json.decode(&Frame::Binary(action)),
+            Err((ErrorCode::UnsupportedFrame, _, None))
+        ));
+
+        let malformed = rmp_serde::to_vec_named(&json!({ "type": "use_card" })).unwrap();
+        let Err((code, _, offending_type)) = Encoding::Msgpack.decode(&Frame::Binary(malformed))
+        else {
+            panic!("missing card_index should be rejected");
+        };
+        assert_eq!(code, ErrorCode::MalformedAction);
+        assert_eq!(offending_type.as_deref(), Some("use_card"));
+    }
+
+    #[test]
+    fn negotiation_picks_encoding_and_checks_version() {
+        assert_eq!(negotiate(PROTOCOL_VERSION, &[]), Ok(Encoding::Json));
+        assert_eq!(
+            negotiate(PROTOCOL_VERSION, &[Feature::Unknown, Feature::Msgpack]),
+            Ok(Encoding::Msgpack)
+        );
+        assert_eq!(
+            negotiate(PROTOCOL_VERSION + 1, &[]).unwrap_err().code,
+            CLOSE_UNSUPPORTED_VERSION
+        );
+    }
+}
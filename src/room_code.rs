@@ -0,0 +1,160 @@
+//! Room codes players type in or read out to each other.
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Error, Result};
+use rand::Rng;
+
+/// Letters and digits that cannot be mistaken for one another: no `0`/`O` and no `1`/`I`/`L`.
+const ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+
+/// Longest character code, so that the number of codes still fits a `u64`.
+const MAX_LEN: usize = 12;
+
+const ADJECTIVES: &[&str] = &[
+    "agile", "amber", "bold", "brave", "breezy", "bright", "calm", "clever", "cosy", "crisp",
+    "curly", "daring", "dizzy", "eager", "fancy", "fast", "fierce", "fluffy", "frosty", "fuzzy",
+    "gentle", "giant", "glad", "golden", "grand", "happy", "hasty", "hidden", "humble", "jolly",
+    "keen", "kind", "lively", "lucky", "merry", "mighty", "misty", "noble", "odd", "plucky",
+    "polite", "proud", "quick", "quiet", "rapid", "rosy", "rusty", "shiny", "silent", "silly",
+    "sleepy", "sly", "snowy", "spicy", "steady", "stormy", "sunny", "swift", "tidy", "tiny",
+    "vivid", "wild", "witty", "zesty",
+];
+
+const ANIMALS: &[&str] = &[
+    "badger", "bat", "bear", "beaver", "bison", "camel", "cat", "cobra", "crab", "crane", "crow",
+    "deer", "dingo", "dog", "duck", "eagle", "eel", "falcon", "ferret", "finch", "fox", "frog",
+    "gecko", "goat", "goose", "hare", "hawk", "heron", "horse", "hyena", "ibis", "koala", "lemur",
+    "lion", "llama", "lynx", "mole", "moose", "mouse", "newt", "otter", "owl", "panda", "parrot",
+    "pig", "puffin", "quail", "rabbit", "raven", "seal", "shark", "sheep", "sloth", "snail",
+    "swan", "tiger", "toad", "trout", "turtle", "viper", "walrus", "whale", "wolf", "yak",
+];
+
+/// Two-digit suffix of word codes, from 10 to 99.
+const NUMBERS: u64 = 90;
+
+/// How room codes are made; parsed from `words` or a code length.
+///
+/// Codes are matched case-insensitively, see [`RoomCodes::normalize`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoomCodes {
+    /// This many characters from an alphabet without look-alikes, such as `K7XQ2`.
+    Characters(usize),
+    /// An adjective, an animal and a number, such as `brave-otter-42`.
+    Words,
+}
+
+impl Default for RoomCodes {
+    fn default() -> Self {
+        RoomCodes::Characters(5)
+    }
+}
+
+impl FromStr for RoomCodes {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "words" {
+            return Ok(RoomCodes::Words);
+        }
+        let len = s
+            .parse()
+            .map_err(|_| anyhow!("Room codes should be `words` or a length, not {s}"))?;
+        RoomCodes::characters(len)
+    }
+}
+
+impl RoomCodes {
+    /// Character codes of length `len`, from 2 to 12.
+    pub fn characters(len: usize) -> Result<Self> {
+        if !(2..=MAX_LEN).contains(&len) {
+            bail!("Room codes should be 2 to {MAX_LEN} characters long, not {len}");
+        }
+        Ok(RoomCodes::Characters(len))
+    }
+
+    /// How many different codes there are.
+    pub fn capacity(self) -> u64 {
+        match self {
+            RoomCodes::Characters(len) => (ALPHABET.len() as u64).pow(len as u32),
+            RoomCodes::Words => ADJECTIVES.len() as u64 * ANIMALS.len() as u64 * NUMBERS,
+        }
+    }
+
+    /// The code numbered `index`, below [`RoomCodes::capacity`]; every index gives a
+    /// different code.
+    pub fn code(self, mut index: u64) -> String {
+        match self {
+            RoomCodes::Characters(len) => (0..len)
+                .map(|_| {
+                    let c = ALPHABET[(index % ALPHABET.len() as u64) as usize];
+                    index /= ALPHABET.len() as u64;
+                    c as char
+                })
+                .collect(),
+            RoomCodes::Words => {
+                let number = index % NUMBERS + 10;
+                index /= NUMBERS;
+                let animal = ANIMALS[(index % ANIMALS.len() as u64) as usize];
+                let adjective = ADJECTIVES[(index / ANIMALS.len() as u64) as usize];
+                format!("{adjective}-{animal}-{number}")
+            }
+        }
+    }
+
+    pub fn random(self, rng: &mut impl Rng) -> String {
+        self.code(rng.gen_range(0..self.capacity()))
+    }
+
+    /// The form codes are looked up by, so that `k7xq2` finds room `K7XQ2`.
+    pub fn normalize(code: &str) -> String {
+        code.trim().to_ascii_lowercase()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn every_index_gives_a_different_code() {
+        for codes in [RoomCodes::Characters(3), RoomCodes::Words] {
+            let all = (0..codes.capacity())
+                .map(|index| RoomCodes::normalize(&codes.code(index)))
+                .collect::<HashSet<_>>();
+            assert_eq!(all.len() as u64, codes.capacity());
+        }
+        assert_eq!(RoomCodes::Characters(12).capacity(), 31u64.pow(12));
+    }
+
+    #[test]
+    fn codes_avoid_look_alikes() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..1000 {
+            let code = RoomCodes::Characters(8).random(&mut rng);
+            assert_eq!(code.len(), 8);
+            assert!(!code.contains(['0', 'O', '1', 'I', 'L']), "{code}");
+        }
+        let code = RoomCodes::Words.random(&mut rng);
+        let [adjective, animal, number] = code.split('-').collect::<Vec<_>>()[..] else {
+            panic!("{code}");
+        };
+        assert!(ADJECTIVES.contains(&adjective));
+        assert!(ANIMALS.contains(&animal));
+        assert!((10..100).contains(&number.parse::<u32>().unwrap()));
+    }
+
+    #[test]
+    fn parses_styles() {
+        assert_eq!("words".parse::<RoomCodes>().unwrap(), RoomCodes::Words);
+        assert_eq!("6".parse::<RoomCodes>().unwrap(), RoomCodes::Characters(6));
+        for invalid in ["1", "13", "six", ""] {
+            assert!(invalid.parse::<RoomCodes>().is_err(), "{invalid}");
+        }
+        assert_eq!(RoomCodes::normalize(" Brave-OTTER-42\n"), "brave-otter-42");
+    }
+}
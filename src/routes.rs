@@ -0,0 +1,170 @@
+//! The HTTP and WebSocket endpoints of the server.
+
+use std::{convert::Infallible, path::PathBuf};
+
+use anyhow::{bail, Result};
+use serde_json::json;
+use warp::{
+    filters::ws::Ws,
+    http::{StatusCode, Uri},
+    hyper::body::Bytes,
+    Filter, Rejection, Reply,
+};
+
+use crate::{assets, game::GameOptions, protocol, server::Server, transport::Connection};
+
+/// Largest `POST /create-game` body accepted, room options are a few hundred bytes.
+const MAX_OPTIONS_LEN: u64 = 16 * 1024;
+
+/// Every endpoint, with the frontend in `static_dir` as the fallback.
+///
+/// The bundled frontend is same-origin, so no CORS headers are sent by default and browsers
+/// keep other sites out. `allowed_origins`, comma separated, lets pages on those hosts call
+/// the API too. Fails on an origin that is not `scheme://host[:port]`.
+pub fn routes(
+    server: Server,
+    static_dir: impl Into<PathBuf>,
+    allowed_origins: Option<&str>,
+) -> Result<impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone> {
+    let cors = match allowed_origins {
+        Some(origins) => Some(
+            warp::cors::cors()
+                .allow_origins(parse_origins(origins)?)
+                .build(),
+        ),
+        None => None,
+    };
+    let create_game = warp::path("create-game")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(options_body())
+        .and_then({
+            let server = server.clone();
+            move |body: Bytes| {
+                let server = server.clone();
+                async move {
+                    let options = if body.is_empty() {
+                        Ok(GameOptions::default())
+                    } else {
+                        serde_json::from_slice(&body).map_err(anyhow::Error::from)
+                    };
+                    let game_code = match options {
+                        Ok(options) => server.new_game(options).await,
+                        Err(err) => Err(err),
+                    };
+                    Ok::<_, Infallible>(match game_code {
+                        Ok(game_code) => warp::reply::with_status(
+                            json!({ "game_code": game_code }).to_string(),
+                            StatusCode::OK,
+                        ),
+                        Err(err) => warp::reply::with_status(
+                            json!({ "error": err.to_string() }).to_string(),
+                            StatusCode::BAD_REQUEST,
+                        ),
+                    })
+                }
+            }
+        });
+
+    let card_sets = warp::path("card-sets")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map({
+            let card_sets = json!({ "card_sets": server.card_sets().names().collect::<Vec<_>>() });
+            move || card_sets.to_string()
+        });
+
+    let game_exist = warp::path("game-exist")
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::get())
+        .and_then({
+            let server = server.clone();
+            move |game_code: String| {
+                let server = server.clone();
+                async move {
+                    let is_game_exist = server.is_game_exist(&game_code).await;
+                    Ok::<_, Infallible>(json!({ "game_exist": is_game_exist }).to_string())
+                }
+            }
+        });
+
+    let join_game = warp::path("game")
+        .and(warp::path::param())
+        .and(warp::ws())
+        .map({
+            let server = server.clone();
+            move |game_code: String, ws: Ws| {
+                let server = server.clone();
+                ws.on_upgrade(|socket| async move {
+                    let _ = server
+                        .add_player_to_game(Connection::websocket(socket), &game_code)
+                        .await;
+                })
+            }
+        });
+
+    let protocol_schema = warp::path!("protocol" / "schema").and(warp::get()).map({
+        let schema = protocol::schema();
+        move || warp::reply::json(&schema)
+    });
+
+    let api = create_game
+        .or(game_exist)
+        .or(join_game)
+        .or(protocol_schema)
+        .or(card_sets);
+    let api = match cors {
+        Some(cors) => api.with(cors).map(boxed_reply).boxed(),
+        None => api.map(boxed_reply).boxed(),
+    };
+    Ok(api.or(assets::static_files(static_dir)))
+}
+
+fn boxed_reply(reply: impl Reply + 'static) -> Box<dyn Reply> {
+    Box::new(reply)
+}
+
+/// The `POST /create-game` body, at most [`MAX_OPTIONS_LEN`] bytes.
+///
+/// A request without `Content-Length` or `Transfer-Encoding` has no body, like the empty
+/// POST curl and hyper send, so it reads as empty instead of being rejected.
+fn options_body() -> impl Filter<Extract = (Bytes,), Error = Rejection> + Clone {
+    let no_body = warp::header::optional::<String>("content-length")
+        .and(warp::header::optional::<String>("transfer-encoding"))
+        .and_then(
+            |length: Option<String>, encoding: Option<String>| async move {
+                match (length, encoding) {
+                    (None, None) => Ok(Bytes::new()),
+                    _ => Err(warp::reject()),
+                }
+            },
+        );
+    warp::body::content_length_limit(MAX_OPTIONS_LEN)
+        .and(warp::body::bytes())
+        .or(no_body)
+        .unify()
+}
+
+/// Splits comma separated origins, checking each the way warp does, which panics instead.
+fn parse_origins(origins: &str) -> Result<Vec<&str>> {
+    origins
+        .split(',')
+        .map(str::trim)
+        .map(|origin| {
+            let valid = origin.contains("://")
+                && origin.parse::<Uri>().is_ok_and(|uri| {
+                    uri.scheme().is_some()
+                        && uri.authority().is_some()
+                        && matches!(
+                            uri.path_and_query().map(|path| path.as_str()),
+                            None | Some("/")
+                        )
+                });
+            if !valid {
+                bail!("Invalid allowed origin {origin:?}, should be like https://example.com");
+            }
+            Ok(origin)
+        })
+        .collect()
+}
@@ -0,0 +1,125 @@
+use std::fmt::Debug;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::stack::Overflow;
+
+/// Decides what happens when a stack fills up: how many points it is worth and what is left
+/// on it afterwards.
+pub trait ScoringRule: Debug + Send + Sync {
+    /// Scores the full stack `values`, bottom first, and removes the scored numbers.
+    fn overflow(&self, values: &mut Vec<i32>) -> Overflow;
+}
+
+/// The scoring rules a room can pick from.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Scoring {
+    /// The player gains the top number, everyone else loses the bottom one.
+    #[default]
+    FirstLast,
+    /// The player gains the sum of the stack, everyone else loses it.
+    SumOfStack,
+    /// The player gains the spread between the largest and smallest number, everyone else
+    /// loses it.
+    MaxMinusMin,
+    /// Scored like `first_last`, but only the bottom half is cleared.
+    KeepTopHalf,
+}
+
+impl Scoring {
+    pub fn rule(self) -> &'static dyn ScoringRule {
+        match self {
+            Scoring::FirstLast => &FirstLast,
+            Scoring::SumOfStack => &SumOfStack,
+            Scoring::MaxMinusMin => &MaxMinusMin,
+            Scoring::KeepTopHalf => &KeepTopHalf,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FirstLast;
+
+impl ScoringRule for FirstLast {
+    fn overflow(&self, values: &mut Vec<i32>) -> Overflow {
+        let overflow = Overflow {
+            other_lost: values.first().copied().unwrap_or_default(),
+            self_gain: values.last().copied().unwrap_or_default(),
+        };
+        values.clear();
+        overflow
+    }
+}
+
+#[derive(Debug)]
+pub struct SumOfStack;
+
+impl ScoringRule for SumOfStack {
+    fn overflow(&self, values: &mut Vec<i32>) -> Overflow {
+        let sum = values.drain(..).fold(0, i32::saturating_add);
+        Overflow {
+            other_lost: sum,
+            self_gain: sum,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MaxMinusMin;
+
+impl ScoringRule for MaxMinusMin {
+    fn overflow(&self, values: &mut Vec<i32>) -> Overflow {
+        let max = values.iter().max().copied().unwrap_or_default();
+        let min = values.iter().min().copied().unwrap_or_default();
+        values.clear();
+        Overflow {
+            other_lost: max.saturating_sub(min),
+            self_gain: max.saturating_sub(min),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct KeepTopHalf;
+
+impl ScoringRule for KeepTopHalf {
+    fn overflow(&self, values: &mut Vec<i32>) -> Overflow {
+        let overflow = Overflow {
+            other_lost: values.first().copied().unwrap_or_default(),
+            self_gain: values.last().copied().unwrap_or_default(),
+        };
+        values.drain(..values.len().div_ceil(2));
+        overflow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(scoring: Scoring, values: &[i32]) -> (i32, i32, Vec<i32>) {
+        let mut values = values.to_vec();
+        let overflow = scoring.rule().overflow(&mut values);
+        (overflow.other_lost, overflow.self_gain, values)
+    }
+
+    #[test]
+    fn rules_score_and_clear() {
+        let values = [2, -3, 5, 1, 4];
+        assert_eq!(score(Scoring::FirstLast, &values), (2, 4, vec![]));
+        assert_eq!(score(Scoring::SumOfStack, &values), (9, 9, vec![]));
+        assert_eq!(score(Scoring::MaxMinusMin, &values), (8, 8, vec![]));
+        assert_eq!(score(Scoring::KeepTopHalf, &values), (2, 4, vec![1, 4]));
+        assert_eq!(score(Scoring::KeepTopHalf, &[1, 2]), (1, 2, vec![2]));
+    }
+
+    #[test]
+    fn rules_saturate() {
+        let values = [i32::MAX, i32::MAX, i32::MIN];
+        assert_eq!(score(Scoring::SumOfStack, &values).0, -1);
+        assert_eq!(score(Scoring::SumOfStack, &values[..2]).0, i32::MAX);
+        assert_eq!(score(Scoring::MaxMinusMin, &values).0, i32::MAX);
+    }
+}
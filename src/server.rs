@@ -1,82 +1,195 @@
-use std::{collections::HashMap, sync::Arc};
+use std::sync::Arc;
 
-use anyhow::{anyhow, Result};
-use futures::SinkExt;
-use rand::{distributions::Alphanumeric, thread_rng, Rng};
-use tokio::sync::Mutex;
-use warp::filters::ws::{self, WebSocket};
+use anyhow::{anyhow, bail, Result};
+use dashmap::{mapref::entry::Entry, DashMap};
+use futures::StreamExt;
+use rand::{thread_rng, Rng};
+use tokio::net::TcpListener;
 
 use crate::{
-    game::Game,
+    card_set::CardSets,
+    game::{Game, GameConfig, GameOptions},
+    outbox::{self, OUTBOX_CAPACITY},
     player::Player,
+    protocol::Frame,
+    room_code::RoomCodes,
+    transport::{self, Connection, Packet},
 };
 
+/// Random codes tried for a new room before looking for a free one in order.
+const RANDOM_CODE_ATTEMPTS: usize = 8;
+
 #[derive(Debug, Clone, Default)]
 pub struct Server {
-    games: Arc<Mutex<HashMap<String, Game>>>,
+    /// Rooms by normalized code. The map is sharded so rooms only contend with the ones in
+    /// their shard, and no shard is ever locked across an `.await`.
+    games: Arc<DashMap<String, Game>>,
+    card_sets: Arc<CardSets>,
+    room_codes: RoomCodes,
 }
 
 impl Server {
-    pub async fn new_game(&self) -> String {
-        let mut game_code = thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(3)
-            .map(|c| c as char)
-            .collect::<String>();
-        let mut games = self.games.lock().await;
-        while games.contains_key(&game_code) {
-            game_code = thread_rng()
-                .sample_iter(&Alphanumeric)
-                .take(3)
-                .map(|c| c as char)
-                .collect::<String>();
+    pub fn new(card_sets: CardSets) -> Self {
+        Self {
+            games: Arc::default(),
+            card_sets: Arc::new(card_sets),
+            room_codes: RoomCodes::default(),
         }
-        let server = self.clone();
-        let game_code_removing = game_code.clone();
-        let remover = move || {
-            let server = server;
-            server.destroy_game(game_code_removing)
-        };
-        games.insert(game_code.clone(), Game::new(game_code.clone(), remover));
-        game_code
+    }
+
+    pub fn with_room_codes(mut self, room_codes: RoomCodes) -> Self {
+        self.room_codes = room_codes;
+        self
+    }
+
+    pub fn card_sets(&self) -> &CardSets {
+        &self.card_sets
+    }
+
+    pub async fn new_game(&self, options: GameOptions) -> Result<String> {
+        let config = GameConfig::new(options, &self.card_sets)?;
+        let capacity = self.room_codes.capacity();
+        if self.games.len() as u64 >= capacity {
+            bail!("No room codes left, all {capacity} are in use");
+        }
+        // Random codes are almost always free; once they keep colliding, every code is
+        // tried in turn from a random one, so that a nearly full space still finds the rest.
+        let mut rng = thread_rng();
+        let start = rng.gen_range(0..capacity);
+        let random = (0..RANDOM_CODE_ATTEMPTS)
+            .map(|_| self.room_codes.random(&mut rng))
+            .collect::<Vec<_>>();
+        let in_order =
+            (0..capacity).map(|offset| self.room_codes.code((start + offset) % capacity));
+        for game_code in random.into_iter().chain(in_order) {
+            if let Entry::Vacant(entry) = self.games.entry(RoomCodes::normalize(&game_code)) {
+                let server = self.clone();
+                let game_code_removing = game_code.clone();
+                let remover = move || {
+                    let server = server;
+                    server.destroy_game(game_code_removing)
+                };
+                entry.insert(Game::new(game_code.clone(), config, remover));
+                return Ok(game_code);
+            }
+        }
+        bail!("No room codes left, all {capacity} are in use");
     }
 
     pub async fn is_game_exist(&self, game_code: &str) -> bool {
-        self.games.lock().await.contains_key(game_code)
+        self.games.contains_key(&RoomCodes::normalize(game_code))
     }
 
     pub async fn destroy_game(self, game_code: String) {
-        let mut games = self.games.lock().await;
-        games.remove(&game_code);
+        self.games.remove(&RoomCodes::normalize(&game_code));
     }
 
     pub async fn add_player_to_game(
         &self,
-        mut player_socket: WebSocket,
+        mut connection: Connection,
         game_code: &str,
     ) -> Result<()> {
-        let mut games = self.games.lock().await;
-        let game = if let Some(game) = games.get_mut(game_code) {
-            game
-        } else {
-            player_socket
-                .send(ws::Message::close_with(1000u16, "Game Not Found"))
-                .await
-                .expect("Should successfully send");
-            player_socket
-                .close()
-                .await
-                .expect("Should successfully close");
+        let added = self
+            .games
+            .get(&RoomCodes::normalize(game_code))
+            .map(|game| game.add_player());
+        let Some((id, action_sender)) = added else {
+            if let Err(err) =
+                transport::close(&mut connection.sink, 1000u16, "Game Not Found").await
+            {
+                eprintln!("ERROR: {err}");
+            }
             return Err(anyhow!("Game Not Found"));
         };
-        let (message_sender, message_recviver) = tokio::sync::mpsc::channel(3);
+        let (message_sender, message_recviver) = outbox::channel(OUTBOX_CAPACITY);
         let new_player = Player::new(message_sender);
         tokio::spawn(crate::player::handle_one_player(
+            id,
             new_player,
-            player_socket,
-            game.action_sender.clone(),
+            connection,
+            action_sender,
             message_recviver,
         ));
         Ok(())
     }
+
+    /// Accepts line-delimited JSON clients on `listener`.
+    ///
+    /// The first line a client sends is the game code to join; after that the connection
+    /// speaks the same protocol as the WebSocket endpoint.
+    pub async fn accept_tcp_players(self, listener: TcpListener) {
+        loop {
+            let socket = match listener.accept().await {
+                Ok((socket, _)) => socket,
+                Err(err) => {
+                    eprintln!("ERROR: {err}");
+                    continue;
+                }
+            };
+            let server = self.clone();
+            tokio::spawn(async move {
+                let mut connection = Connection::tcp(socket);
+                let game_code = match connection.stream.next().await {
+                    Some(Ok(Packet::Frame(Frame::Text(game_code)))) => game_code,
+                    _ => return,
+                };
+                let _ = server
+                    .add_player_to_game(connection, game_code.trim())
+                    .await;
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn games_are_created_and_removed_concurrently() {
+        let server = Server::default();
+        let creating = (0..200)
+            .map(|_| {
+                let server = server.clone();
+                tokio::spawn(async move { server.new_game(GameOptions::default()).await })
+            })
+            .collect::<Vec<_>>();
+        let mut codes = HashSet::new();
+        for game_code in creating {
+            codes.insert(game_code.await.unwrap().unwrap());
+        }
+        assert_eq!(codes.len(), 200);
+        for game_code in &codes {
+            assert!(server.is_game_exist(game_code).await);
+        }
+
+        let game_code = codes.iter().next().unwrap().clone();
+        server.clone().destroy_game(game_code.clone()).await;
+        assert!(!server.is_game_exist(&game_code).await);
+        assert_eq!(server.games.len(), 199);
+    }
+
+    #[tokio::test]
+    async fn codes_are_case_insensitive() {
+        let server = Server::default().with_room_codes(RoomCodes::Words);
+        let game_code = server.new_game(GameOptions::default()).await.unwrap();
+        assert!(server.is_game_exist(&game_code.to_uppercase()).await);
+
+        let server = Server::default();
+        let game_code = server.new_game(GameOptions::default()).await.unwrap();
+        assert_eq!(game_code.len(), 5);
+        assert!(server.is_game_exist(&game_code.to_lowercase()).await);
+    }
+
+    #[tokio::test]
+    async fn running_out_of_codes_is_an_error() {
+        let room_codes = RoomCodes::characters(2).unwrap();
+        let server = Server::default().with_room_codes(room_codes);
+        for _ in 0..room_codes.capacity() {
+            server.new_game(GameOptions::default()).await.unwrap();
+        }
+        assert!(server.new_game(GameOptions::default()).await.is_err());
+    }
 }
@@ -0,0 +1,404 @@
+//! Headless games between bots, to tune card sets and scoring rules with data instead of
+//! guesses.
+//!
+//! Bots play through [`GameState`], so games follow exactly the rules of a room.
+
+use std::{collections::BTreeMap, fmt::Write, sync::Arc};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bot,
+    game::GameConfig,
+    player::PlayerAction,
+    stack::{Card, Overflow, Stack},
+    state::{Event, GameState},
+    team::Teams,
+};
+
+/// How a bot picks the card it plays.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    /// Any card, at random.
+    Random,
+    /// The card with the largest point swing right now.
+    Greedy,
+    /// The card leaving the shortest stack, so the next player is unlikely to overflow it.
+    Cautious,
+}
+
+impl Strategy {
+    fn name(self) -> &'static str {
+        match self {
+            Strategy::Random => "random",
+            Strategy::Greedy => "greedy",
+            Strategy::Cautious => "cautious",
+        }
+    }
+
+    fn choose(self, cards: &[Card], stack: &Stack, rng: &mut impl Rng) -> usize {
+        let outcome = |card: &Card| {
+            let mut stack = stack.clone();
+            let overflow: Overflow = stack.use_card(card).into_iter().sum();
+            (
+                overflow.self_gain.saturating_add(overflow.other_lost),
+                stack.values().len(),
+            )
+        };
+        let best = |key: &dyn Fn(&Card) -> (i32, i32)| {
+            (0..cards.len())
+                .max_by_key(|index| key(&cards[*index]))
+                .unwrap_or_default()
+        };
+        match self {
+            Strategy::Random => rng.gen_range(0..cards.len()),
+            Strategy::Greedy => best(&|card| (outcome(card).0, 0)),
+            Strategy::Cautious => best(&|card| {
+                let (swing, len) = outcome(card);
+                (-(len as i32), swing)
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulationOptions {
+    pub games: usize,
+    /// One bot per strategy, the game seats them at random.
+    pub strategies: Vec<Strategy>,
+    /// Games still running after this many rounds end as they stand.
+    pub max_rounds: usize,
+    pub seed: u64,
+}
+
+impl Default for SimulationOptions {
+    fn default() -> Self {
+        Self {
+            games: 1000,
+            strategies: vec![Strategy::Greedy, Strategy::Random],
+            max_rounds: 200,
+            seed: 0,
+        }
+    }
+}
+
+/// How often cards with one kind of action overflow the stack, and for how much.
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct ActionImpact {
+    /// Played cards with this action, counted once per card.
+    pub plays: usize,
+    pub overflow_rate: f64,
+    /// Average swing of those plays, zero for plays that did not overflow.
+    pub average_swing: f64,
+}
+
+/// Aggregated results of a simulation run.
+///
+/// The swing of a play is what the player gained plus what every opponent lost.
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct Report {
+    pub games: usize,
+    pub average_turns: f64,
+    /// Games cut off by `max_rounds`, scored by the points left.
+    pub unfinished: usize,
+    /// Share of the wins going to every seat, a tie splits a win.
+    pub seat_win_rate: Vec<f64>,
+    /// Win rate of the first seat above an even share.
+    pub first_player_advantage: f64,
+    pub strategy_win_rate: BTreeMap<Strategy, f64>,
+    /// Share of plays that overflowed the stack at least once.
+    pub overflow_frequency: f64,
+    /// Number of overflowing plays for every swing.
+    pub point_swing: BTreeMap<i32, usize>,
+    pub actions: BTreeMap<&'static str, ActionImpact>,
+}
+
+impl Report {
+    /// The report as `metric,key,value` rows.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("metric,key,value\n");
+        let mut row = |metric: &str, key: &dyn ToString, value: &dyn ToString| {
+            let _ = writeln!(csv, "{metric},{},{}", key.to_string(), value.to_string());
+        };
+        row("games", &"", &self.games);
+        row("average_turns", &"", &self.average_turns);
+        row("unfinished", &"", &self.unfinished);
+        row("first_player_advantage", &"", &self.first_player_advantage);
+        row("overflow_frequency", &"", &self.overflow_frequency);
+        for (seat, rate) in self.seat_win_rate.iter().enumerate() {
+            row("seat_win_rate", &seat, rate);
+        }
+        for (strategy, rate) in &self.strategy_win_rate {
+            row("strategy_win_rate", &strategy.name(), rate);
+        }
+        for (swing, count) in &self.point_swing {
+            row("point_swing", swing, count);
+        }
+        for (action, impact) in &self.actions {
+            row("action_plays", action, &impact.plays);
+            row("action_overflow_rate", action, &impact.overflow_rate);
+            row("action_average_swing", action, &impact.average_swing);
+        }
+        csv
+    }
+}
+
+/// Runs `options.games` games of a room played with `config` and reports on them.
+pub fn simulate(options: &SimulationOptions, config: &GameConfig) -> Report {
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let mut config = config.clone();
+    config.round_limit = Some(
+        config
+            .round_limit
+            .map_or(options.max_rounds, |limit| limit.min(options.max_rounds)),
+    );
+    let mut tally = Tally::new(options.strategies.len());
+    for _ in 0..options.games {
+        let rounds = play_game(&options.strategies, &config, &mut rng, &mut tally);
+        if rounds >= options.max_rounds {
+            tally.unfinished += 1;
+        }
+    }
+    tally.report(options)
+}
+
+/// Raw counts gathered while games are played.
+#[derive(Default)]
+struct Tally {
+    turns: usize,
+    unfinished: usize,
+    plays: usize,
+    overflowing_plays: usize,
+    seat_wins: Vec<f64>,
+    strategy_wins: BTreeMap<Strategy, f64>,
+    point_swing: BTreeMap<i32, usize>,
+    /// Plays, overflowing plays and total swing for every action.
+    actions: BTreeMap<&'static str, (usize, usize, i64)>,
+}
+
+impl Tally {
+    fn new(seats: usize) -> Self {
+        Self {
+            seat_wins: vec![0.0; seats],
+            ..Self::default()
+        }
+    }
+
+    fn play(&mut self, card: &Card, overflowed: bool, swing: i32) {
+        self.plays += 1;
+        if overflowed {
+            self.overflowing_plays += 1;
+            *self.point_swing.entry(swing).or_default() += 1;
+        }
+        let mut names = card
+            .actions
+            .iter()
+            .map(|action| action.name())
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        names.dedup();
+        for name in names {
+            let (plays, overflows, total) = self.actions.entry(name).or_default();
+            *plays += 1;
+            *overflows += usize::from(overflowed);
+            *total += i64::from(swing);
+        }
+    }
+
+    fn report(self, options: &SimulationOptions) -> Report {
+        let ratio = |part: f64, whole: usize| {
+            if whole == 0 {
+                0.0
+            } else {
+                part / whole as f64
+            }
+        };
+        let seats = self.seat_wins.len();
+        let seat_win_rate = self
+            .seat_wins
+            .iter()
+            .map(|wins| ratio(*wins, options.games))
+            .collect::<Vec<_>>();
+        let per_strategy =
+            options
+                .strategies
+                .iter()
+                .fold(BTreeMap::<_, usize>::new(), |mut count, strategy| {
+                    *count.entry(*strategy).or_default() += 1;
+                    count
+                });
+        Report {
+            games: options.games,
+            average_turns: ratio(self.turns as f64, options.games),
+            unfinished: self.unfinished,
+            first_player_advantage: seat_win_rate
+                .first()
+                .map_or(0.0, |rate| rate - ratio(1.0, seats)),
+            seat_win_rate,
+            strategy_win_rate: per_strategy
+                .into_iter()
+                .map(|(strategy, seats)| {
+                    let wins = self.strategy_wins.get(&strategy).copied().unwrap_or(0.0);
+                    (strategy, ratio(wins, options.games * seats))
+                })
+                .collect(),
+            overflow_frequency: ratio(self.overflowing_plays as f64, self.plays),
+            point_swing: self.point_swing,
+            actions: self
+                .actions
+                .into_iter()
+                .map(|(name, (plays, overflows, total))| {
+                    let impact = ActionImpact {
+                        plays,
+                        overflow_rate: ratio(overflows as f64, plays),
+                        average_swing: ratio(total as f64, plays),
+                    };
+                    (name, impact)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Plays one game with a bot per strategy, bot `id` playing `strategies[id]`.
+fn play_game(
+    strategies: &[Strategy],
+    config: &GameConfig,
+    rng: &mut impl Rng,
+    tally: &mut Tally,
+) -> usize {
+    let names = (0..strategies.len())
+        .map(|id| (id, Arc::from(format!("bot{id}"))))
+        .collect::<BTreeMap<_, _>>();
+    let teams = config.teams.map(|options| {
+        let mut teams = Teams::new(options.count);
+        for id in names.keys() {
+            teams.join(*id);
+        }
+        teams
+    });
+    let (mut state, mut events) = GameState::new(config.clone(), names, teams, rng.gen());
+    let mut seating = Vec::new();
+    loop {
+        for event in events {
+            match event {
+                Event::Started {
+                    seating: seated, ..
+                } => seating = seated,
+                Event::TurnStarted { .. } => tally.turns += 1,
+                Event::CardPlayed {
+                    id,
+                    card,
+                    overflows,
+                    changes,
+                    ..
+                } => {
+                    let lost = changes
+                        .iter()
+                        .filter(|(other, _)| **other != id)
+                        .map(|(_, change)| change.min(&0).saturating_neg())
+                        .fold(0, i32::saturating_add);
+                    let swing = changes
+                        .get(&id)
+                        .copied()
+                        .unwrap_or_default()
+                        .saturating_add(lost);
+                    tally.play(&card, !overflows.is_empty(), swing);
+                }
+                Event::Ended { winners, .. } => {
+                    let share = 1.0 / winners.len() as f64;
+                    for id in winners {
+                        if let Some(seat) = seating.iter().position(|seated| *seated == id) {
+                            tally.seat_wins[seat] += share;
+                        }
+                        *tally.strategy_wins.entry(strategies[id]).or_default() += share;
+                    }
+                }
+                _ => (),
+            }
+        }
+        if state.is_over() {
+            return state.rounds();
+        }
+        let id = state.playing();
+        let card_index = strategies[id].choose(state.cards(), state.stack(), rng);
+        let target = if config.targeted {
+            let scores = state.scores().into_values().collect::<Vec<_>>();
+            state
+                .name(&id)
+                .and_then(|name| bot::choose_target(&scores, name))
+                .cloned()
+        } else {
+            None
+        };
+        events = state.apply(id, &PlayerAction::UseCard { card_index, target });
+        if events
+            .iter()
+            .any(|event| matches!(event, Event::Rejected { .. }))
+        {
+            events = state.apply(id, &PlayerAction::Pass);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{card_set::CardSet, scoring::Scoring, stack::CardDistribution};
+
+    use super::*;
+
+    #[test]
+    fn reports_are_reproducible_and_consistent() {
+        let options = SimulationOptions {
+            games: 200,
+            strategies: vec![Strategy::Greedy, Strategy::Random, Strategy::Cautious],
+            ..SimulationOptions::default()
+        };
+        let config = GameConfig::default();
+        let report = simulate(&options, &config);
+        assert_eq!(report, simulate(&options, &config));
+
+        assert_eq!(report.games, 200);
+        assert!(report.average_turns > 0.0);
+        // Every game has a winner unless the last players all drop out at once.
+        let wins = report.seat_win_rate.iter().sum::<f64>();
+        assert!(wins > 0.9 && wins <= 1.0 + 1e-9);
+        assert_eq!(report.strategy_win_rate.len(), 3);
+        assert!((0.0..=1.0).contains(&report.overflow_frequency));
+        assert!(report.actions.contains_key("push"));
+        let overflowing_plays = report.point_swing.values().sum::<usize>();
+        assert!(overflowing_plays > 0);
+        assert!(
+            report.strategy_win_rate[&Strategy::Greedy]
+                > report.strategy_win_rate[&Strategy::Random]
+        );
+
+        let csv = report.to_csv();
+        assert!(csv.starts_with("metric,key,value\n"));
+        assert!(csv.contains("\nstrategy_win_rate,greedy,"));
+    }
+
+    #[test]
+    fn huge_overflows_saturate() {
+        let mut card_set = CardSet::default();
+        card_set.action_weights.mul = 20;
+        card_set.mul.min = 1000;
+        card_set.mul.weights = vec![1];
+        card_set.validate().unwrap();
+        let config = GameConfig {
+            cards: Arc::new(CardDistribution::new(&card_set).unwrap()),
+            scoring: Scoring::SumOfStack,
+            ..GameConfig::default()
+        };
+        let options = SimulationOptions {
+            games: 20,
+            strategies: vec![Strategy::Greedy, Strategy::Cautious],
+            ..SimulationOptions::default()
+        };
+        let report = simulate(&options, &config);
+        assert!(report.point_swing.contains_key(&i32::MAX));
+    }
+}
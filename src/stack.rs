@@ -1,41 +1,83 @@
+use anyhow::Result;
 use rand::{
-    distributions::{Distribution, Standard, WeightedIndex},
+    distributions::{Distribution, WeightedIndex},
     Rng,
 };
+use std::{fmt, iter::Sum};
+
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    card_set::{ActionWeights, CardSet, ValueRange},
+    scoring::Scoring,
+};
+
+/// Points scored when the stack fills up, see [`crate::scoring::ScoringRule`].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
 pub struct Overflow {
     pub other_lost: i32,
     pub self_gain: i32,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+impl Sum for Overflow {
+    fn sum<I: Iterator<Item = Overflow>>(iter: I) -> Self {
+        iter.fold(
+            Overflow {
+                other_lost: 0,
+                self_gain: 0,
+            },
+            |total, overflow| Overflow {
+                other_lost: total.other_lost.saturating_add(overflow.other_lost),
+                self_gain: total.self_gain.saturating_add(overflow.self_gain),
+            },
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 pub struct Stack {
     vec: Vec<i32>,
     len: usize,
+    #[serde(skip)]
+    scoring: Scoring,
 }
 
 impl Stack {
     pub fn new(len: usize) -> Self {
+        Self::with_scoring(len, Scoring::default())
+    }
+
+    pub fn with_scoring(len: usize, scoring: Scoring) -> Self {
         Self {
             vec: Vec::with_capacity(10),
             len,
+            scoring,
         }
     }
 
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         self.len
     }
 
+    /// The numbers on the stack, bottom first.
+    pub fn values(&self) -> &[i32] {
+        &self.vec
+    }
+
+    /// Takes one off the length, down to `min_len`, never growing it. Numbers above the new
+    /// length stay until the next push overflows the stack.
+    pub fn shrink(&mut self, min_len: usize) {
+        if self.len > min_len {
+            self.len -= 1;
+        }
+    }
+
     pub fn push(&mut self, num: i32) -> Option<Overflow> {
         self.vec.push(num);
-        if self.vec.len() == self.len {
-            let overflow = Overflow {
-                other_lost: *self.vec.first().unwrap(),
-                self_gain: *self.vec.last().unwrap(),
-            };
-            self.vec.clear();
-            Some(overflow)
+        if self.vec.len() >= self.len {
+            Some(self.scoring.rule().overflow(&mut self.vec))
         } else {
             None
         }
@@ -50,20 +92,98 @@ impl Stack {
     }
 
     pub fn add(&mut self, num: i32) {
-        self.vec.last_mut().map(|n| *n += num);
+        if let Some(n) = self.vec.last_mut() {
+            *n = n.saturating_add(num);
+        }
     }
 
     pub fn neg(&mut self) {
-        self.vec.last_mut().map(|n| *n *= -1);
+        if let Some(n) = self.vec.last_mut() {
+            *n = n.saturating_neg();
+        }
+    }
+
+    pub fn dup(&mut self) -> Option<Overflow> {
+        let top = *self.vec.last()?;
+        self.push(top)
+    }
+
+    pub fn swap(&mut self) {
+        let len = self.vec.len();
+        if len >= 2 {
+            self.vec.swap(len - 1, len - 2);
+        }
+    }
+
+    pub fn mul(&mut self, num: i32) {
+        if let Some(n) = self.vec.last_mut() {
+            *n = n.saturating_mul(num);
+        }
+    }
+
+    /// Moves the top number to the bottom `num` times, or the bottom one to the top when
+    /// `num` is negative.
+    pub fn rotate(&mut self, num: i32) {
+        if self.vec.is_empty() {
+            return;
+        }
+        let shift = num.unsigned_abs() as usize % self.vec.len();
+        if num >= 0 {
+            self.vec.rotate_right(shift);
+        } else {
+            self.vec.rotate_left(shift);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.vec.clear();
+    }
+
+    /// Sorts the stack so that the largest number ends up on top.
+    pub fn sort(&mut self) {
+        self.vec.sort_unstable();
     }
 
     pub fn use_action(&mut self, action: &Action) -> Option<Overflow> {
         match action {
-          Action::Push(num) => self.push(*num),
-          Action::Pop => { self.pop(); None },
-          Action::Reverse => { self.reverse(); None },
-          Action::Add(num) => { self.add(*num); None },
-          Action::Neg => { self.neg(); None },
+            Action::Push(num) => self.push(*num),
+            Action::Pop => {
+                self.pop();
+                None
+            }
+            Action::Reverse => {
+                self.reverse();
+                None
+            }
+            Action::Add(num) => {
+                self.add(*num);
+                None
+            }
+            Action::Neg => {
+                self.neg();
+                None
+            }
+            Action::Dup => self.dup(),
+            Action::Swap => {
+                self.swap();
+                None
+            }
+            Action::Mul(num) => {
+                self.mul(*num);
+                None
+            }
+            Action::Rotate(num) => {
+                self.rotate(*num);
+                None
+            }
+            Action::Clear => {
+                self.clear();
+                None
+            }
+            Action::Sort => {
+                self.sort();
+                None
+            }
         }
     }
 
@@ -78,29 +198,103 @@ impl Default for Stack {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 pub struct Card {
     pub actions: Vec<Action>,
+    /// A one-shot effect on the game, resolved after the actions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power: Option<Power>,
 }
 
+/// Game-level effects a card can carry besides its stack actions.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Power {
+    /// The next player loses their turn.
+    Skip,
+    /// Turns go the other way round the table.
+    Reverse,
+    /// Takes a point from the next player, or from the target in a targeted game.
+    Steal,
+    /// Shows the player the cards the next player will get.
+    Peek,
+    /// The player loses no points from the next overflow that would hurt them.
+    Shield,
+}
+
+/// Samples random cards following a [`CardSet`].
+#[derive(Debug)]
 pub struct CardDistribution {
     action_number_weight: WeightedIndex<u32>,
     card_weight: WeightedIndex<u32>,
-    push_number_weight: WeightedIndex<u32>,
-    add_number_weight: WeightedIndex<u32>,
-    neg_weight: WeightedIndex<u32>,
+    push_number: ValueDistribution,
+    add_number: ValueDistribution,
+    mul_number: ValueDistribution,
+    rotate_number: ValueDistribution,
+    power_weight: WeightedIndex<u32>,
+}
+
+#[derive(Debug)]
+struct ValueDistribution {
+    min: i32,
+    weight: WeightedIndex<u32>,
+    negate_weight: WeightedIndex<u32>,
+}
+
+impl ValueDistribution {
+    fn new(range: &ValueRange) -> Result<Self> {
+        Ok(Self {
+            min: range.min,
+            weight: WeightedIndex::new(&range.weights)?,
+            negate_weight: WeightedIndex::new([range.keep_sign, range.negate])?,
+        })
+    }
+}
+
+impl Distribution<i32> for ValueDistribution {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> i32 {
+        let num = self.min + self.weight.sample(rng) as i32;
+        if self.negate_weight.sample(rng) == 1 {
+            -num
+        } else {
+            num
+        }
+    }
+}
+
+impl CardDistribution {
+    pub fn new(card_set: &CardSet) -> Result<Self> {
+        card_set.validate()?;
+        let ActionWeights {
+            push,
+            pop,
+            reverse,
+            add,
+            neg,
+            dup,
+            swap,
+            mul,
+            rotate,
+            clear,
+            sort,
+        } = card_set.action_weights;
+        Ok(Self {
+            action_number_weight: WeightedIndex::new(&card_set.actions_per_card)?,
+            card_weight: WeightedIndex::new([
+                push, pop, reverse, add, neg, dup, swap, mul, rotate, clear, sort,
+            ])?,
+            push_number: ValueDistribution::new(&card_set.push)?,
+            add_number: ValueDistribution::new(&card_set.add)?,
+            mul_number: ValueDistribution::new(&card_set.mul)?,
+            rotate_number: ValueDistribution::new(&card_set.rotate)?,
+            power_weight: WeightedIndex::new(card_set.powers.weights())?,
+        })
+    }
 }
 
 impl Default for CardDistribution {
     fn default() -> Self {
-        Self {
-            action_number_weight: WeightedIndex::new([4, 6, 3, 1]).expect("Should success"),
-            card_weight: WeightedIndex::new([7, 3, 3, 2, 1]).expect("Should success"),
-            push_number_weight: WeightedIndex::new([1, 1, 1, 2, 3, 4, 8, 8, 8, 1])
-                .expect("Should success"),
-            add_number_weight: WeightedIndex::new([1, 2, 3, 3]).expect("Should success"),
-            neg_weight: WeightedIndex::new([1, 7]).expect("Should success"),
-        }
+        Self::new(&CardSet::default()).expect("Should success")
     }
 }
 
@@ -111,37 +305,395 @@ impl Distribution<Card> for CardDistribution {
         actions.fill_with(|| {
             let action_type = self.card_weight.sample(rng);
             match action_type {
-                0 => {
-                    let num = (self.push_number_weight.sample(rng) as i32 - 9)
-                        * if self.neg_weight.sample(rng) == 1 {
-                            -1
-                        } else {
-                            1
-                        };
-                    Action::Push(num)
-                }
+                0 => Action::Push(self.push_number.sample(rng)),
                 1 => Action::Pop,
                 2 => Action::Reverse,
-                3 => {
-                    let num = (self.add_number_weight.sample(rng) as i32 + 1)
-                        * if Standard.sample(rng) { -1 } else { 1 };
-                    Action::Add(num)
-                }
+                3 => Action::Add(self.add_number.sample(rng)),
                 4 => Action::Neg,
+                5 => Action::Dup,
+                6 => Action::Swap,
+                7 => Action::Mul(self.mul_number.sample(rng)),
+                8 => Action::Rotate(self.rotate_number.sample(rng)),
+                9 => Action::Clear,
+                10 => Action::Sort,
                 _ => unreachable!(),
             }
         });
 
-        Card { actions }
+        let power = match self.power_weight.sample(rng) {
+            0 => None,
+            1 => Some(Power::Skip),
+            2 => Some(Power::Reverse),
+            3 => Some(Power::Steal),
+            4 => Some(Power::Peek),
+            5 => Some(Power::Shield),
+            _ => unreachable!(),
+        };
+
+        Card { actions, power }
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(tag = "action_type", content = "num", rename_all="snake_case")]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(tag = "action_type", content = "num", rename_all = "snake_case")]
 pub enum Action {
     Push(i32),
     Pop,
     Reverse,
     Add(i32),
     Neg,
-}
\ No newline at end of file
+    Dup,
+    Swap,
+    Mul(i32),
+    Rotate(i32),
+    Clear,
+    Sort,
+}
+
+impl Action {
+    /// The kind of action, without its number.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Push(_) => "push",
+            Action::Pop => "pop",
+            Action::Reverse => "reverse",
+            Action::Add(_) => "add",
+            Action::Neg => "neg",
+            Action::Dup => "dup",
+            Action::Swap => "swap",
+            Action::Mul(_) => "mul",
+            Action::Rotate(_) => "rotate",
+            Action::Clear => "clear",
+            Action::Sort => "sort",
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Push(num) => write!(f, "push {num}"),
+            Action::Pop => write!(f, "pop"),
+            Action::Reverse => write!(f, "reverse"),
+            Action::Add(num) => write!(f, "add {num}"),
+            Action::Neg => write!(f, "neg"),
+            Action::Dup => write!(f, "dup"),
+            Action::Swap => write!(f, "swap"),
+            Action::Mul(num) => write!(f, "mul {num}"),
+            Action::Rotate(num) => write!(f, "rotate {num}"),
+            Action::Clear => write!(f, "clear"),
+            Action::Sort => write!(f, "sort"),
+        }
+    }
+}
+
+impl fmt::Display for Power {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Power::Skip => write!(f, "skip"),
+            Power::Reverse => write!(f, "reverse turns"),
+            Power::Steal => write!(f, "steal"),
+            Power::Peek => write!(f, "peek"),
+            Power::Shield => write!(f, "shield"),
+        }
+    }
+}
+
+impl fmt::Display for Card {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, action) in self.actions.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{action}")?;
+        }
+        if let Some(power) = self.power {
+            write!(f, " + {power}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use rand::SeedableRng;
+
+    use crate::card_set::PowerWeights;
+
+    use super::*;
+
+    fn stack_of(values: &[i32]) -> Stack {
+        let mut stack = Stack::new(10);
+        for value in values {
+            stack.push(*value);
+        }
+        stack
+    }
+
+    #[test]
+    fn new_actions_change_the_stack() {
+        let cases = [
+            (Action::Dup, vec![1, 2, 3, 3]),
+            (Action::Swap, vec![1, 3, 2]),
+            (Action::Mul(-2), vec![1, 2, -6]),
+            (Action::Rotate(1), vec![3, 1, 2]),
+            (Action::Rotate(-4), vec![2, 3, 1]),
+            (Action::Clear, vec![]),
+        ];
+        for (action, expected) in cases {
+            let mut stack = stack_of(&[1, 2, 3]);
+            assert!(stack.use_action(&action).is_none(), "{action}");
+            assert_eq!(stack.values(), expected, "{action}");
+        }
+
+        let mut stack = stack_of(&[3, -1, 2]);
+        stack.use_action(&Action::Sort);
+        assert_eq!(stack.values(), [-1, 2, 3]);
+
+        let mut stack = Stack::new(10);
+        for action in [Action::Dup, Action::Swap, Action::Rotate(3), Action::Sort] {
+            stack.use_action(&action);
+        }
+        assert!(stack.values().is_empty());
+    }
+
+    #[test]
+    fn original_actions_change_the_stack() {
+        let cases = [
+            (Action::Push(4), vec![1, 2, 3, 4]),
+            (Action::Pop, vec![1, 2]),
+            (Action::Reverse, vec![3, 2, 1]),
+            (Action::Add(-5), vec![1, 2, -2]),
+            (Action::Neg, vec![1, 2, -3]),
+        ];
+        for (action, expected) in cases {
+            let mut stack = stack_of(&[1, 2, 3]);
+            assert!(stack.use_action(&action).is_none(), "{action}");
+            assert_eq!(stack.values(), expected, "{action}");
+        }
+    }
+
+    #[test]
+    fn actions_on_an_empty_stack_do_nothing() {
+        let actions = [
+            Action::Pop,
+            Action::Reverse,
+            Action::Add(3),
+            Action::Neg,
+            Action::Dup,
+            Action::Swap,
+            Action::Mul(2),
+            Action::Rotate(-2),
+            Action::Clear,
+            Action::Sort,
+        ];
+        let mut stack = Stack::new(10);
+        for action in actions {
+            assert!(stack.use_action(&action).is_none(), "{action}");
+            assert!(stack.values().is_empty(), "{action}");
+        }
+    }
+
+    #[test]
+    fn single_numbers_are_left_alone() {
+        for action in [
+            Action::Reverse,
+            Action::Swap,
+            Action::Rotate(5),
+            Action::Sort,
+        ] {
+            let mut stack = stack_of(&[7]);
+            stack.use_action(&action);
+            assert_eq!(stack.values(), [7], "{action}");
+        }
+        let mut stack = stack_of(&[1, 2, 3]);
+        stack.rotate(3);
+        assert_eq!(stack.values(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn push_at_capacity_overflows() {
+        let mut stack = stack_of(&[-2, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(stack.push(1).is_none());
+        assert_eq!(stack.values().len(), 9);
+        assert_eq!(
+            stack.push(6),
+            Some(Overflow {
+                other_lost: -2,
+                self_gain: 6,
+            })
+        );
+        assert!(stack.values().is_empty());
+
+        let mut stack = Stack::new(1);
+        let overflow = stack.push(-3).unwrap();
+        assert_eq!((overflow.other_lost, overflow.self_gain), (-3, -3));
+    }
+
+    #[test]
+    fn a_card_can_overflow_several_times() {
+        let mut stack = Stack::new(2);
+        let card = Card {
+            actions: vec![
+                Action::Push(1),
+                Action::Push(2),
+                Action::Push(3),
+                Action::Dup,
+                Action::Push(5),
+            ],
+            power: None,
+        };
+        let overflows = stack.use_card(&card);
+        let pairs = overflows
+            .iter()
+            .map(|overflow| (overflow.other_lost, overflow.self_gain))
+            .collect::<Vec<_>>();
+        assert_eq!(pairs, [(1, 2), (3, 3)]);
+        assert_eq!(stack.values(), [5]);
+
+        let total: Overflow = overflows.into_iter().sum();
+        assert_eq!((total.other_lost, total.self_gain), (4, 5));
+    }
+
+    #[test]
+    fn sampled_cards_follow_the_card_set() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(11);
+        let card_set = CardSet::default();
+        let cards = CardDistribution::new(&card_set).unwrap();
+        let samples = 20_000;
+        let mut lengths = [0usize; 4];
+        let mut kinds = BTreeMap::<&str, usize>::new();
+        for _ in 0..samples {
+            let card = rng.sample(&cards);
+            assert!(card.power.is_none());
+            lengths[card.actions.len() - 1] += 1;
+            for action in &card.actions {
+                *kinds.entry(action.name()).or_default() += 1;
+                match action {
+                    Action::Push(num) => assert!((-9..=9).contains(num)),
+                    Action::Add(num) => assert!((1..=4).contains(&num.abs())),
+                    _ => (),
+                }
+            }
+        }
+
+        let share = |count: usize, total: usize| count as f64 / total as f64;
+        let weight_share = |weight: u32, weights: &[u32]| {
+            f64::from(weight) / f64::from(weights.iter().sum::<u32>())
+        };
+        for (count, weight) in lengths.iter().zip(&card_set.actions_per_card) {
+            let expected = weight_share(*weight, &card_set.actions_per_card);
+            assert!((share(*count, samples) - expected).abs() < 0.02);
+        }
+        let weights = card_set.action_weights;
+        let action_weights = [
+            ("push", weights.push),
+            ("pop", weights.pop),
+            ("reverse", weights.reverse),
+            ("add", weights.add),
+            ("neg", weights.neg),
+        ];
+        let all = action_weights.map(|(_, weight)| weight);
+        let actions = kinds.values().sum();
+        for (name, weight) in action_weights {
+            let expected = weight_share(weight, &all);
+            assert!(
+                (share(kinds[name], actions) - expected).abs() < 0.02,
+                "{name}"
+            );
+        }
+        assert_eq!(kinds.len(), 5, "actions without weight are never dealt");
+    }
+
+    #[test]
+    fn sampled_numbers_and_powers_follow_their_weights() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+        let range = ValueRange {
+            min: 2,
+            weights: vec![1, 0, 3],
+            keep_sign: 1,
+            negate: 1,
+        };
+        let numbers = ValueDistribution::new(&range).unwrap();
+        let samples = 20_000;
+        let mut counts = BTreeMap::<i32, usize>::new();
+        for _ in 0..samples {
+            *counts.entry(rng.sample(&numbers)).or_default() += 1;
+        }
+        assert_eq!(counts.keys().copied().collect::<Vec<_>>(), [-4, -2, 2, 4]);
+        let share = |num| counts[&num] as f64 / samples as f64;
+        assert!((share(4) + share(-4) - 0.75).abs() < 0.02);
+        assert!((share(-2) + share(-4) - 0.5).abs() < 0.02);
+
+        let card_set = CardSet {
+            powers: PowerWeights {
+                none: 1,
+                skip: 1,
+                ..PowerWeights::default()
+            },
+            ..CardSet::default()
+        };
+        let cards = CardDistribution::new(&card_set).unwrap();
+        let skips = (0..samples)
+            .filter(|_| rng.sample(&cards).power == Some(Power::Skip))
+            .count();
+        assert!((skips as f64 / samples as f64 - 0.5).abs() < 0.02);
+    }
+
+    #[test]
+    fn dup_can_overflow() {
+        let mut stack = stack_of(&[-4, 0, 0, 0, 0, 0, 0, 0, 5]);
+        let overflow = stack.use_action(&Action::Dup).unwrap();
+        assert_eq!((overflow.other_lost, overflow.self_gain), (-4, 5));
+        assert!(stack.values().is_empty());
+    }
+
+    #[test]
+    fn arithmetic_saturates() {
+        let mut stack = stack_of(&[i32::MAX]);
+        stack.add(1);
+        assert_eq!(stack.values(), [i32::MAX]);
+        stack.mul(-2);
+        assert_eq!(stack.values(), [i32::MIN]);
+        stack.neg();
+        assert_eq!(stack.values(), [i32::MAX]);
+        stack.mul(i32::MAX);
+        assert_eq!(stack.values(), [i32::MAX]);
+    }
+
+    #[test]
+    fn shrinking_overflows_sooner() {
+        let mut stack = stack_of(&[1, 2, 3]);
+        for _ in 0..10 {
+            stack.shrink(3);
+        }
+        assert_eq!(stack.len(), 3);
+        let overflow = stack.push(4).unwrap();
+        assert_eq!((overflow.other_lost, overflow.self_gain), (1, 4));
+    }
+
+    #[test]
+    fn shrinking_never_grows() {
+        let mut stack = Stack::new(10);
+        stack.shrink(20);
+        assert_eq!(stack.len(), 10);
+    }
+
+    #[test]
+    fn wire_format_is_unchanged() {
+        let card = Card {
+            actions: vec![Action::Push(3), Action::Pop, Action::Rotate(2)],
+            power: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&card).unwrap(),
+            serde_json::json!({ "actions": [
+                { "action_type": "push", "num": 3 },
+                { "action_type": "pop" },
+                { "action_type": "rotate", "num": 2 },
+            ] })
+        );
+    }
+}
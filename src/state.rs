@@ -0,0 +1,1160 @@
+//! The rules of a running game, as a synchronous state machine.
+//!
+//! [`GameState`] takes the actions of the players one at a time and answers with the
+//! [`Event`]s they caused. It owns its random number generator, so a game started from the
+//! same seed and fed the same actions always plays out the same way. [`crate::game::Game`]
+//! drives it and turns the events into messages for the players; the simulator drives it
+//! with bots.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{
+    deck::Deck,
+    game::GameConfig,
+    player::{ErrorCode, PlayerAction, Score},
+    stack::{Card, Overflow, Power, Stack},
+    team::{TeamPoints, Teams},
+};
+
+/// Points every player starts with.
+pub const STARTING_POINTS: i32 = 10;
+/// Fresh cards dealt every turn outside of deck mode.
+const CARDS_PER_TURN: usize = 3;
+/// Length of the stack when the game starts, before sudden death shrinks it.
+pub const STACK_LEN: usize = 10;
+
+/// Something that happened in the game, in the order it happened.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// The game started, every player with `point` points, seated in turn order.
+    Started { seating: Vec<usize>, point: i32 },
+    /// The hand of `id` changed, in deck mode.
+    Hand { id: usize, cards: Vec<Card> },
+    /// It is the turn of `id`, who may play from `cards`. Always the last event of a call,
+    /// the state then waits for that player.
+    TurnStarted {
+        id: usize,
+        cards: Vec<Card>,
+        stack: Stack,
+        scores: BTreeMap<usize, Score>,
+    },
+    /// `id` may play another card this turn; `scores` are the points after the last play.
+    PlayAgain {
+        id: usize,
+        cards: Vec<Card>,
+        stack: Stack,
+        scores: BTreeMap<usize, Score>,
+    },
+    /// `id` played `card`, at `target` in a targeted game; `changes` are the points every
+    /// player still in the game got from its overflows.
+    CardPlayed {
+        id: usize,
+        card: Card,
+        target: Option<usize>,
+        overflows: Vec<Overflow>,
+        changes: BTreeMap<usize, i32>,
+    },
+    /// `id` paid the discard cost to get rid of `card`.
+    Discarded { id: usize, card: Card },
+    /// `id` ended their turn without playing the cards they had left.
+    Passed { id: usize },
+    /// The power of the card `id` played took effect, on `target` if it affects one player.
+    PowerUsed {
+        id: usize,
+        power: Power,
+        target: Option<usize>,
+    },
+    /// `id` played a peek card and sees the cards `of` will get.
+    Peeked {
+        id: usize,
+        of: usize,
+        cards: Vec<Card>,
+    },
+    /// What a card would do, only for `id` who asked for a preview.
+    Previewed {
+        id: usize,
+        stack_after: Stack,
+        overflows: Vec<Overflow>,
+        changes: BTreeMap<usize, i32>,
+    },
+    /// `id` ran out of points and is out of the game.
+    Lost { id: usize },
+    /// The action `action` from `id` was not allowed.
+    Rejected {
+        id: usize,
+        action: &'static str,
+        code: ErrorCode,
+        message: Arc<str>,
+    },
+    /// The game is over; a tie has several winners and a winning team wins as a whole.
+    Ended {
+        winners: Vec<usize>,
+        winning_teams: Vec<usize>,
+    },
+}
+
+#[derive(Default, PartialEq, Eq, Debug)]
+struct PlayerState {
+    point: i32,
+}
+
+/// Everything about a game from its start to its end, without any connection to players.
+#[derive(Debug)]
+pub struct GameState {
+    /// Names of the players at the table, including those who are out but not those who left.
+    names: BTreeMap<usize, Arc<str>>,
+    /// Players still in the game.
+    player_state: BTreeMap<usize, PlayerState>,
+    /// Full rounds played, counted every time the turn wraps around `turn_order`.
+    rounds: usize,
+    /// Set once the point target or the round limit is reached.
+    finished: bool,
+    /// Sides that climbed to the point target, the only ones who can win then.
+    reached_target: BTreeSet<usize>,
+    /// Seating order, players who are out keep their seat.
+    turn_order: Vec<usize>,
+    /// Turns go forward through `turn_order`, until a reverse card is played.
+    clockwise: bool,
+    /// Set by a skip card, the next player loses their turn.
+    skip_next: bool,
+    /// Players protected from the next overflow that would cost them points.
+    shields: BTreeSet<usize>,
+    /// Cards already revealed by a peek card, dealt on that player's next turn.
+    peeked: BTreeMap<usize, Vec<Card>>,
+    /// Whose turn it is, the cards they may still play and how many they already played.
+    playing: usize,
+    cards: Vec<Card>,
+    plays: usize,
+    teams: Option<Teams>,
+    stack: Stack,
+    deck: Option<Deck>,
+    config: GameConfig,
+    rng: StdRng,
+}
+
+impl GameState {
+    /// Seats `names` at random, deals and starts the first turn.
+    pub fn new(
+        config: GameConfig,
+        names: BTreeMap<usize, Arc<str>>,
+        teams: Option<Teams>,
+        seed: u64,
+    ) -> (Self, Vec<Event>) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let turn_order = match &teams {
+            Some(teams) => teams.turn_order(&mut rng),
+            None => {
+                let mut seating = names.keys().copied().collect::<Vec<_>>();
+                seating.shuffle(&mut rng);
+                seating
+            }
+        };
+        let mut state = Self {
+            player_state: names
+                .keys()
+                .map(|id| {
+                    let state = PlayerState {
+                        point: STARTING_POINTS,
+                    };
+                    (*id, state)
+                })
+                .collect(),
+            names,
+            rounds: 0,
+            finished: false,
+            reached_target: BTreeSet::new(),
+            // Seating is random, so the first seat plays first.
+            playing: turn_order.first().copied().unwrap_or_default(),
+            turn_order,
+            clockwise: true,
+            skip_next: false,
+            shields: BTreeSet::new(),
+            peeked: BTreeMap::new(),
+            cards: Vec::new(),
+            plays: 0,
+            teams,
+            stack: Stack::with_scoring(STACK_LEN, config.scoring),
+            deck: config
+                .deck
+                .map(|options| Deck::new(options, &config.cards, &mut rng)),
+            config,
+            rng,
+        };
+
+        let mut events = vec![Event::Started {
+            seating: state.turn_order.clone(),
+            point: STARTING_POINTS,
+        }];
+        if let Some(deck) = &mut state.deck {
+            for id in state.names.keys() {
+                deck.draw(*id, &mut state.rng);
+                events.push(Event::Hand {
+                    id: *id,
+                    cards: deck.hand(id).to_vec(),
+                });
+            }
+        }
+        state.start_turn(&mut events);
+        (state, events)
+    }
+
+    /// Applies a game action from `id`. Lobby actions, chat and leaving are up to the caller.
+    pub fn apply(&mut self, id: usize, action: &PlayerAction) -> Vec<Event> {
+        let reject = |code, message: &str| {
+            vec![Event::Rejected {
+                id,
+                action: action.type_name(),
+                code,
+                message: message.into(),
+            }]
+        };
+        if self.is_over() {
+            return reject(ErrorCode::IllegalInPhase, "Game has ended");
+        }
+        let mut events = Vec::new();
+        match action {
+            PlayerAction::Start | PlayerAction::PickTeam { .. } => {
+                return reject(ErrorCode::IllegalInPhase, "Game has already started");
+            }
+            PlayerAction::UseCard { .. }
+            | PlayerAction::Discard { .. }
+            | PlayerAction::Preview { .. }
+            | PlayerAction::Pass
+                if id != self.playing =>
+            {
+                return reject(ErrorCode::NotYourTurn, "Not your turn");
+            }
+            PlayerAction::Pass => {
+                events.push(Event::Passed { id });
+                self.end_turn(&mut events);
+            }
+            PlayerAction::Preview { card_index, .. } => {
+                let target = match self.find_target(action, false, id) {
+                    Ok(target) => target,
+                    Err(message) => return reject(ErrorCode::InvalidTarget, &message),
+                };
+                match self.cards.get(*card_index) {
+                    Some(card) => events.push(self.preview(id, card, target)),
+                    None => {
+                        let message = format!("There are only {} cards", self.cards.len());
+                        return reject(ErrorCode::InvalidCardIndex, &message);
+                    }
+                }
+            }
+            PlayerAction::UseCard { card_index, .. } | PlayerAction::Discard { card_index } => {
+                let required = matches!(action, PlayerAction::UseCard { .. });
+                let target = match self.find_target(action, required, id) {
+                    Ok(target) => target,
+                    Err(message) => return reject(ErrorCode::InvalidTarget, &message),
+                };
+                if *card_index >= self.cards.len() {
+                    let message = format!("There are only {} cards", self.cards.len());
+                    return reject(ErrorCode::InvalidCardIndex, &message);
+                }
+                self.play(id, *card_index, target, required, &mut events);
+            }
+            _ => (),
+        }
+        events
+    }
+
+    /// Takes a player who left the room out of the game, passing the turn on if it was theirs.
+    pub fn leave(&mut self, id: usize) -> Vec<Event> {
+        let mut events = Vec::new();
+        if self.is_over() {
+            return events;
+        }
+        self.names.remove(&id);
+        self.remove_player(&id);
+        if id == self.playing {
+            self.end_turn(&mut events);
+        } else if self.is_over() {
+            self.end_game(&mut events);
+        }
+        events
+    }
+
+    #[inline]
+    pub fn is_over(&self) -> bool {
+        self.finished || self.side_scores().len() <= 1
+    }
+
+    #[inline]
+    pub fn playing(&self) -> usize {
+        self.playing
+    }
+
+    /// The cards the playing player may still play this turn.
+    #[inline]
+    pub fn cards(&self) -> &[Card] {
+        &self.cards
+    }
+
+    #[inline]
+    pub fn stack(&self) -> &Stack {
+        &self.stack
+    }
+
+    #[inline]
+    pub fn rounds(&self) -> usize {
+        self.rounds
+    }
+
+    #[inline]
+    pub fn name(&self, id: &usize) -> Option<&Arc<str>> {
+        self.names.get(id)
+    }
+
+    /// A scoreboard line for every player at the table.
+    pub fn scores(&self) -> BTreeMap<usize, Score> {
+        self.names
+            .iter()
+            .map(|(id, name)| {
+                let score = Score {
+                    name: name.clone(),
+                    point: self.player_state.get(id).map(|state| state.point),
+                    hand_size: self.deck.as_ref().map(|deck| deck.hand_size(id)),
+                    team: self.teams.as_ref().and_then(|teams| teams.team(id)),
+                };
+                (*id, score)
+            })
+            .collect()
+    }
+
+    /// Plays or discards the card at `card_index`, then ends the turn once no play is left.
+    fn play(
+        &mut self,
+        id: usize,
+        card_index: usize,
+        target: Option<usize>,
+        use_card: bool,
+        events: &mut Vec<Event>,
+    ) {
+        let card = self.cards.remove(card_index);
+        if let Some(deck) = &mut self.deck {
+            deck.play(&id, card_index);
+        }
+        let before = self.side_scores();
+        if use_card {
+            let overflows = self.stack.use_card(&card);
+            let changes = self.score_overflows(&overflows, id, target);
+            let power = card.power;
+            events.push(Event::CardPlayed {
+                id,
+                card,
+                target,
+                overflows,
+                changes,
+            });
+            self.settle_points(&before, events);
+            if let Some(power) = power {
+                self.use_power(power, id, target, events);
+            }
+        } else {
+            self.charge(id, self.config.discard_cost);
+            events.push(Event::Discarded { id, card });
+            self.settle_points(&before, events);
+        }
+        self.plays += 1;
+        if self.plays >= self.config.plays_per_turn
+            || self.cards.is_empty()
+            || !self.player_state.contains_key(&id)
+            || self.is_over()
+        {
+            self.end_turn(events);
+        } else {
+            events.push(Event::PlayAgain {
+                id,
+                cards: self.cards.clone(),
+                stack: self.stack.clone(),
+                scores: self.scores(),
+            });
+        }
+    }
+
+    /// Deals the cards of the playing player and announces their turn, moving on past players
+    /// with nothing to play. The game ends once nobody has a card left.
+    fn start_turn(&mut self, events: &mut Vec<Event>) {
+        for _ in 0..self.turn_order.len() {
+            if self.is_over() {
+                break;
+            }
+            if let Some(deck) = &mut self.deck {
+                // Cards of players who left are back in the deck by now.
+                let held = deck.hand_size(&self.playing);
+                deck.draw(self.playing, &mut self.rng);
+                if deck.hand_size(&self.playing) != held {
+                    events.push(Event::Hand {
+                        id: self.playing,
+                        cards: deck.hand(&self.playing).to_vec(),
+                    });
+                }
+            }
+            self.cards = self.round_cards(self.playing);
+            self.plays = 0;
+            if !self.cards.is_empty() {
+                events.push(Event::TurnStarted {
+                    id: self.playing,
+                    cards: self.cards.clone(),
+                    stack: self.stack.clone(),
+                    scores: self.scores(),
+                });
+                return;
+            }
+            // Only possible in deck mode, when the deck is smaller than all hands together.
+            self.playing = self.next_id(self.playing);
+        }
+        self.finished = true;
+        self.end_game(events);
+    }
+
+    /// Draws back up to a full hand in deck mode, then passes the turn on.
+    fn end_turn(&mut self, events: &mut Vec<Event>) {
+        let id = self.playing;
+        if self.player_state.contains_key(&id) {
+            if let Some(deck) = &mut self.deck {
+                deck.draw(id, &mut self.rng);
+                events.push(Event::Hand {
+                    id,
+                    cards: deck.hand(&id).to_vec(),
+                });
+            }
+        }
+        if self.is_over() {
+            self.end_game(events);
+        } else {
+            self.playing = self.advance_turn(id);
+            self.start_turn(events);
+        }
+    }
+
+    fn end_game(&self, events: &mut Vec<Event>) {
+        let winners = self.winners();
+        let winning_teams = self.teams.as_ref().map_or_else(Vec::new, |teams| {
+            winners
+                .iter()
+                .filter_map(|id| teams.team(id))
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect::<Vec<_>>()
+        });
+        let winners = match &self.teams {
+            Some(teams) => winning_teams
+                .iter()
+                .flat_map(|team| teams.members(*team))
+                .filter(|id| self.names.contains_key(id))
+                .collect(),
+            None => winners,
+        };
+        events.push(Event::Ended {
+            winners,
+            winning_teams,
+        });
+    }
+
+    /// Applies the points of `overflows` caused by `playing_id`, a shield absorbing a loss.
+    ///
+    /// With pooled points the shield of one member covers the whole team, so every member
+    /// keeps the same points.
+    fn score_overflows(
+        &mut self,
+        overflows: &[Overflow],
+        playing_id: usize,
+        target: Option<usize>,
+    ) -> BTreeMap<usize, i32> {
+        if overflows.is_empty() {
+            return BTreeMap::new();
+        }
+        let mut changes = self.point_changes(overflows, playing_id, target);
+        let mut used = BTreeSet::new();
+        for (id, change) in &mut changes {
+            if *change < 0 {
+                if let Some(holder) = self.shield_of(id) {
+                    used.insert(holder);
+                    *change = 0;
+                }
+            }
+        }
+        for holder in &used {
+            self.shields.remove(holder);
+        }
+        for (id, change) in &changes {
+            if let Some(state) = self.player_state.get_mut(id) {
+                state.point = state.point.saturating_add(*change);
+            }
+        }
+        changes
+    }
+
+    /// Who holds the shield protecting `id`: `id` themselves, or a teammate when points are
+    /// pooled.
+    fn shield_of(&self, id: &usize) -> Option<usize> {
+        if self.shields.contains(id) {
+            return Some(*id);
+        }
+        if !self.pooled() {
+            return None;
+        }
+        self.shields
+            .iter()
+            .find(|holder| self.side(holder) == self.side(id))
+            .copied()
+    }
+
+    /// Resolves the power of a card `playing_id` just played.
+    fn use_power(
+        &mut self,
+        power: Power,
+        playing_id: usize,
+        target: Option<usize>,
+        events: &mut Vec<Event>,
+    ) {
+        let next_id = self.next_id(playing_id);
+        let affected = match power {
+            Power::Skip => {
+                self.skip_next = true;
+                Some(next_id)
+            }
+            Power::Reverse => {
+                self.clockwise = !self.clockwise;
+                None
+            }
+            Power::Steal => {
+                let victim = target.unwrap_or(next_id);
+                let changes = self.steal_changes(playing_id, victim);
+                if !changes.is_empty() {
+                    let before = self.side_scores();
+                    for (id, change) in changes {
+                        if let Some(state) = self.player_state.get_mut(&id) {
+                            state.point = state.point.saturating_add(change);
+                        }
+                    }
+                    self.settle_points(&before, events);
+                }
+                Some(victim)
+            }
+            Power::Peek => {
+                let cards = self.peek_cards(next_id);
+                events.push(Event::Peeked {
+                    id: playing_id,
+                    of: next_id,
+                    cards,
+                });
+                Some(next_id)
+            }
+            Power::Shield => {
+                self.shields.insert(playing_id);
+                None
+            }
+        };
+        events.push(Event::PowerUsed {
+            id: playing_id,
+            power,
+            target: affected,
+        });
+    }
+
+    /// Knocks out players without points and checks the point target after a score change,
+    /// `before` being the side scores before it.
+    ///
+    /// Only a side that climbs to the target reaches it, so a summed team starting above the
+    /// target has to drop below it first.
+    fn settle_points(&mut self, before: &BTreeMap<usize, i32>, events: &mut Vec<Event>) {
+        let lost_ids = self
+            .player_state
+            .iter()
+            .filter(|(_, state)| state.point <= 0)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in lost_ids {
+            self.remove_player(&id);
+            events.push(Event::Lost { id });
+        }
+        if let Some(target) = self.config.point_target {
+            self.reached_target = self
+                .side_scores()
+                .into_iter()
+                .filter(|(side, score)| {
+                    *score >= target && before.get(side).is_some_and(|before| *before < target)
+                })
+                .map(|(side, _)| side)
+                .collect();
+            if !self.reached_target.is_empty() {
+                self.finished = true;
+            }
+        }
+    }
+
+    /// Resolves the opponent named by the `target` of `action` in a targeted game. Names are
+    /// unique, the lobby turns down a name that is taken.
+    ///
+    /// The target has to be still in the game and on another side than `playing_id`. It is
+    /// only optional where `required` is `false`, and must be left out when the room does not
+    /// use targeted attacks.
+    fn find_target(
+        &self,
+        action: &PlayerAction,
+        required: bool,
+        playing_id: usize,
+    ) -> Result<Option<usize>, String> {
+        let Some(target) = action.target() else {
+            if required && self.config.targeted {
+                return Err("Pick a target for this card".to_string());
+            }
+            return Ok(None);
+        };
+        if !self.config.targeted {
+            return Err("This room does not use targeted attacks".to_string());
+        }
+        let id = self
+            .names
+            .iter()
+            .find(|(id, name)| *name == target && self.player_state.contains_key(id))
+            .map(|(id, _)| *id)
+            .ok_or_else(|| format!("{target} is not in the game"))?;
+        if self.side(&id) == self.side(&playing_id) {
+            return Err(format!("{target} is not an opponent"));
+        }
+        Ok(Some(id))
+    }
+
+    /// Plays `card` on a copy of the stack to show `id` what would happen.
+    fn preview(&self, id: usize, card: &Card, target: Option<usize>) -> Event {
+        let mut stack_after = self.stack.clone();
+        let overflows = stack_after.use_card(card);
+        let mut changes = self
+            .point_changes(&overflows, id, target)
+            .into_iter()
+            .map(|(id, change)| {
+                if change < 0 && self.shield_of(&id).is_some() {
+                    (id, 0)
+                } else {
+                    (id, change)
+                }
+            })
+            .collect::<BTreeMap<_, _>>();
+        if card.power == Some(Power::Steal) {
+            let victim = target.unwrap_or(self.next_id(id));
+            for (id, change) in self.steal_changes(id, victim) {
+                let total = changes.entry(id).or_default();
+                *total = total.saturating_add(change);
+            }
+        }
+        Event::Previewed {
+            id,
+            stack_after,
+            overflows,
+            changes,
+        }
+    }
+
+    #[inline]
+    fn remove_player(&mut self, id: &usize) {
+        self.player_state.remove(id);
+        if let Some(deck) = &mut self.deck {
+            deck.remove_player(id);
+        }
+    }
+
+    /// The team of `id` in team mode; otherwise every player is on a side of their own.
+    #[inline]
+    fn side(&self, id: &usize) -> usize {
+        self.teams
+            .as_ref()
+            .and_then(|teams| teams.team(id))
+            .unwrap_or(*id)
+    }
+
+    #[inline]
+    fn pooled(&self) -> bool {
+        self.config
+            .teams
+            .is_some_and(|teams| teams.points == TeamPoints::Pooled)
+    }
+
+    /// Takes `cost` points from `id`, and from their whole team when points are pooled.
+    fn charge(&mut self, id: usize, cost: i32) {
+        for id in self.sharing_points(id) {
+            if let Some(state) = self.player_state.get_mut(&id) {
+                state.point = state.point.saturating_sub(cost);
+            }
+        }
+    }
+
+    /// `id` and, when points are pooled, their teammates still in the game.
+    fn sharing_points(&self, id: usize) -> Vec<usize> {
+        let side = self.side(&id);
+        let pooled = self.pooled();
+        self.player_state
+            .keys()
+            .filter(|other| **other == id || (pooled && self.side(other) == side))
+            .copied()
+            .collect()
+    }
+
+    /// The points a steal card from `playing_id` moves: one from `victim` to the thief, from
+    /// and to their whole teams when points are pooled, and none between teammates.
+    fn steal_changes(&self, playing_id: usize, victim: usize) -> BTreeMap<usize, i32> {
+        let mut changes = BTreeMap::new();
+        if self.side(&victim) == self.side(&playing_id) {
+            return changes;
+        }
+        for id in self.sharing_points(victim) {
+            *changes.entry(id).or_default() -= 1;
+        }
+        for id in self.sharing_points(playing_id) {
+            *changes.entry(id).or_default() += 1;
+        }
+        changes
+    }
+
+    /// The score of every side still in the game: the shared points of a pooled team, the
+    /// sum of its members otherwise.
+    fn side_scores(&self) -> BTreeMap<usize, i32> {
+        let mut scores = BTreeMap::new();
+        for (id, state) in &self.player_state {
+            let score = scores.entry(self.side(id)).or_insert(0);
+            if self.pooled() {
+                *score = state.point;
+            } else {
+                *score = score.saturating_add(state.point);
+            }
+        }
+        scores
+    }
+
+    /// Players still in the game on the sides with the best score, several on a tie. Once
+    /// the point target is reached only the sides that reached it are compared.
+    fn winners(&self) -> Vec<usize> {
+        let mut scores = self.side_scores();
+        if !self.reached_target.is_empty() {
+            scores.retain(|side, _| self.reached_target.contains(side));
+        }
+        let Some(best) = scores.values().max() else {
+            return Vec::new();
+        };
+        self.player_state
+            .keys()
+            .filter(|id| scores.get(&self.side(id)) == Some(best))
+            .copied()
+            .collect()
+    }
+
+    /// Moves the turn on from `id`, past the next player after a skip card.
+    fn advance_turn(&mut self, id: usize) -> usize {
+        let steps = if std::mem::take(&mut self.skip_next) {
+            2
+        } else {
+            1
+        };
+        (0..steps).fold(id, |id, _| self.step_turn(id))
+    }
+
+    /// Moves the turn to the next player, counting rounds and applying the round limit and
+    /// sudden death whenever the turn wraps around.
+    fn step_turn(&mut self, id: usize) -> usize {
+        let next_id = self.next_id(id);
+        let seat = |id| self.turn_order.iter().position(|seated| *seated == id);
+        let wrapped = if self.clockwise {
+            seat(next_id) <= seat(id)
+        } else {
+            seat(next_id) >= seat(id)
+        };
+        if wrapped {
+            self.rounds += 1;
+            if self
+                .config
+                .round_limit
+                .is_some_and(|limit| self.rounds >= limit)
+            {
+                self.finished = true;
+            }
+            if let Some(sudden_death) = self.config.sudden_death {
+                if self.rounds >= sudden_death.after_rounds {
+                    self.stack.shrink(sudden_death.min_len);
+                }
+            }
+        }
+        next_id
+    }
+
+    /// How many points every player still in the game gets from `overflows` caused by
+    /// `playing_id`. Only opposing sides lose, or only `target` when there is one;
+    /// teammates share the gain and the loss when points are pooled.
+    fn point_changes(
+        &self,
+        overflows: &[Overflow],
+        playing_id: usize,
+        target: Option<usize>,
+    ) -> BTreeMap<usize, i32> {
+        let Overflow {
+            self_gain: gain,
+            other_lost: lose,
+        } = overflows.iter().copied().sum();
+        let playing_side = self.side(&playing_id);
+        self.player_state
+            .keys()
+            .map(|id| {
+                let hit = match target {
+                    Some(target) => {
+                        *id == target || (self.pooled() && self.side(id) == self.side(&target))
+                    }
+                    None => self.side(id) != playing_side,
+                };
+                let change = if hit {
+                    lose.saturating_neg()
+                } else if self.side(id) != playing_side {
+                    0
+                } else if *id == playing_id || self.pooled() {
+                    gain
+                } else {
+                    0
+                };
+                (*id, change)
+            })
+            .collect()
+    }
+
+    /// The next player in `turn_order`, in the current direction, who is still in the game.
+    #[inline]
+    fn next_id(&self, id: usize) -> usize {
+        let len = self.turn_order.len();
+        let seat = self
+            .turn_order
+            .iter()
+            .position(|seated| *seated == id)
+            .unwrap_or_default();
+        (1..=len)
+            .map(|step| {
+                if self.clockwise {
+                    (seat + step) % len
+                } else {
+                    (seat + len - step) % len
+                }
+            })
+            .map(|seat| self.turn_order[seat])
+            .find(|seated| self.player_state.contains_key(seated))
+            .unwrap_or(id)
+    }
+
+    /// The cards `id` may play this turn: their hand in deck mode, fresh cards otherwise.
+    #[inline]
+    fn round_cards(&mut self, id: usize) -> Vec<Card> {
+        if let Some(deck) = &self.deck {
+            return deck.hand(&id).to_vec();
+        }
+        if let Some(cards) = self.peeked.remove(&id) {
+            return cards;
+        }
+        (0..CARDS_PER_TURN)
+            .map(|_| self.rng.sample(&*self.config.cards))
+            .collect()
+    }
+
+    /// The cards `id` will get on their next turn, dealt in advance outside of deck mode.
+    fn peek_cards(&mut self, id: usize) -> Vec<Card> {
+        if let Some(deck) = &self.deck {
+            return deck.hand(&id).to_vec();
+        }
+        if !self.peeked.contains_key(&id) {
+            let cards = self.round_cards(id);
+            self.peeked.insert(id, cards);
+        }
+        self.peeked[&id].clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{card_set::CardSets, deck::DeckOptions, game::GameOptions, team::TeamOptions};
+
+    use super::*;
+
+    fn names(count: usize) -> BTreeMap<usize, Arc<str>> {
+        (1..=count)
+            .map(|id| (id, Arc::from(format!("p{id}"))))
+            .collect()
+    }
+
+    #[test]
+    fn turns_follow_direction_and_skips() {
+        let (mut state, _) = GameState::new(GameConfig::default(), names(4), None, 3);
+        state.turn_order = vec![1, 2, 3, 4];
+        assert_eq!(state.advance_turn(1), 2);
+
+        state.skip_next = true;
+        assert_eq!(state.advance_turn(2), 4);
+        assert_eq!(state.rounds, 0);
+
+        state.clockwise = false;
+        state.player_state.remove(&3);
+        assert_eq!(state.advance_turn(4), 2);
+        assert_eq!(state.advance_turn(2), 1);
+        assert_eq!(state.advance_turn(1), 4);
+        assert_eq!(state.rounds, 1);
+    }
+
+    #[test]
+    fn same_seed_same_game() {
+        let play = || {
+            let (mut state, mut events) = GameState::new(GameConfig::default(), names(3), None, 7);
+            for _ in 0..30 {
+                let playing = state.playing();
+                events.extend(state.apply(
+                    playing,
+                    &PlayerAction::UseCard {
+                        card_index: 0,
+                        target: None,
+                    },
+                ));
+            }
+            events
+        };
+        let events = play();
+        assert_eq!(events, play());
+        assert!(matches!(events[0], Event::Started { .. }));
+        let turns = events
+            .iter()
+            .filter(|event| matches!(event, Event::TurnStarted { .. }))
+            .count();
+        assert!(turns > 1);
+    }
+
+    #[test]
+    fn turn_rules_are_enforced() {
+        let options = GameOptions {
+            plays_per_turn: 2,
+            ..GameOptions::default()
+        };
+        let config = GameConfig::new(options, &CardSets::default()).unwrap();
+        let (mut state, events) = GameState::new(config, names(2), None, 1);
+        let playing = state.playing();
+        assert!(matches!(
+            events.last(),
+            Some(Event::TurnStarted { id, cards, .. }) if *id == playing && cards.len() == 3
+        ));
+        let other = if playing == 1 { 2 } else { 1 };
+
+        let rejected = |events: &[Event], expected: ErrorCode| matches!(events, [Event::Rejected { code, .. }] if *code == expected);
+        assert!(rejected(
+            &state.apply(other, &PlayerAction::Pass),
+            ErrorCode::NotYourTurn
+        ));
+        assert!(rejected(
+            &state.apply(playing, &PlayerAction::Discard { card_index: 3 }),
+            ErrorCode::InvalidCardIndex
+        ));
+        let target = PlayerAction::UseCard {
+            card_index: 0,
+            target: Some("p1".into()),
+        };
+        assert!(rejected(
+            &state.apply(playing, &target),
+            ErrorCode::InvalidTarget
+        ));
+        assert!(rejected(
+            &state.apply(playing, &PlayerAction::Start),
+            ErrorCode::IllegalInPhase
+        ));
+
+        let events = state.apply(playing, &PlayerAction::Discard { card_index: 0 });
+        assert!(matches!(events[0], Event::Discarded { id, .. } if id == playing));
+        assert!(matches!(
+            &events[1],
+            Event::PlayAgain { cards, scores, .. }
+                if cards.len() == 2 && scores[&playing].point == Some(STARTING_POINTS - 1)
+        ));
+        assert_eq!(state.scores()[&playing].point, Some(STARTING_POINTS - 1));
+
+        let events = state.apply(playing, &PlayerAction::Pass);
+        assert!(matches!(
+            events[..],
+            [Event::Passed { id }, Event::TurnStarted { id: next, .. }]
+                if id == playing && next == other
+        ));
+    }
+
+    #[test]
+    fn leaving_passes_the_turn_or_ends_the_game() {
+        let (mut state, _) = GameState::new(GameConfig::default(), names(3), None, 2);
+        let playing = state.playing();
+        let next = state.next_id(playing);
+        let events = state.leave(playing);
+        assert!(matches!(events[..], [Event::TurnStarted { id, .. }] if id == next));
+        assert_eq!(state.playing(), next);
+        assert!(!state.scores().contains_key(&playing));
+
+        let events = state.leave(next);
+        assert!(state.is_over());
+        assert!(matches!(&events[..], [Event::Ended { winners, .. }] if winners.len() == 1));
+        assert!(state.leave(state.playing()).is_empty());
+    }
+
+    #[test]
+    fn pooled_shield_covers_the_whole_team() {
+        let config = GameConfig {
+            teams: Some(TeamOptions::default()),
+            ..GameConfig::default()
+        };
+        let mut teams = Teams::new(2);
+        for id in 1..=4 {
+            teams.join(id);
+        }
+        let (mut state, _) = GameState::new(config, names(4), Some(teams), 5);
+        let (shielded, mate, opponent) = (1, 3, 2);
+        assert_eq!(state.side(&shielded), state.side(&mate));
+        state.shields.insert(shielded);
+
+        let overflow = [Overflow {
+            other_lost: 3,
+            self_gain: 2,
+        }];
+        let changes = state.score_overflows(&overflow, opponent, None);
+        assert_eq!((changes[&shielded], changes[&mate]), (0, 0));
+        assert!(state.shields.is_empty());
+
+        state.score_overflows(&overflow, opponent, None);
+        let points = state.scores();
+        assert_eq!(points[&shielded].point, Some(STARTING_POINTS - 3));
+        assert_eq!(points[&mate].point, points[&shielded].point);
+        assert_eq!(points[&opponent].point, Some(STARTING_POINTS + 4));
+    }
+
+    #[test]
+    fn preview_matches_a_steal() {
+        let (mut state, _) = GameState::new(GameConfig::default(), names(2), None, 8);
+        let playing = state.playing();
+        let other = state.next_id(playing);
+        state.cards = vec![Card {
+            actions: Vec::new(),
+            power: Some(Power::Steal),
+        }];
+        let events = state.apply(
+            playing,
+            &PlayerAction::Preview {
+                card_index: 0,
+                target: None,
+            },
+        );
+        let [Event::Previewed { changes, .. }] = &events[..] else {
+            panic!("{events:?}");
+        };
+        assert_eq!(changes, &BTreeMap::from([(playing, 1), (other, -1)]));
+
+        state.apply(
+            playing,
+            &PlayerAction::UseCard {
+                card_index: 0,
+                target: None,
+            },
+        );
+        let scores = state.scores();
+        assert_eq!(scores[&playing].point, Some(STARTING_POINTS + 1));
+        assert_eq!(scores[&other].point, Some(STARTING_POINTS - 1));
+    }
+
+    #[test]
+    fn point_target_is_reached_from_below() {
+        let options = GameOptions {
+            point_target: Some(STARTING_POINTS),
+            ..GameOptions::default()
+        };
+        assert!(GameConfig::new(options, &CardSets::default()).is_err());
+
+        let config = GameConfig {
+            point_target: Some(STARTING_POINTS + 2),
+            ..GameConfig::default()
+        };
+        let (mut state, _) = GameState::new(config.clone(), names(3), None, 6);
+        let before = state.side_scores();
+        state.charge(1, -1);
+        state.settle_points(&before, &mut Vec::new());
+        assert!(!state.is_over());
+        let before = state.side_scores();
+        state.charge(1, -1);
+        state.settle_points(&before, &mut Vec::new());
+        assert!(state.is_over());
+
+        // Summed teams of two start at twice the starting points, above the target.
+        let config = GameConfig {
+            teams: Some(TeamOptions {
+                count: 2,
+                points: TeamPoints::Summed,
+            }),
+            ..config
+        };
+        let mut teams = Teams::new(2);
+        for id in 1..=4 {
+            teams.join(id);
+        }
+        let (mut state, _) = GameState::new(config, names(4), Some(teams), 6);
+        let before = state.side_scores();
+        state.charge(1, -1);
+        state.settle_points(&before, &mut Vec::new());
+        assert!(!state.is_over());
+    }
+
+    #[test]
+    fn only_sides_reaching_the_target_win() {
+        let config = GameConfig {
+            point_target: Some(STARTING_POINTS + 2),
+            teams: Some(TeamOptions {
+                count: 2,
+                points: TeamPoints::Summed,
+            }),
+            ..GameConfig::default()
+        };
+        assert!(config.check_team_sizes([1, 1]).is_ok());
+        assert!(config.check_team_sizes([2, 1]).is_err());
+
+        // Three players on team 0 start at 30, far above the target of 12 that team 1 climbs to.
+        let mut teams = Teams::new(2);
+        for id in 1..=4 {
+            teams.join(id);
+        }
+        teams.pick(2, 0);
+        let (mut state, _) = GameState::new(config, names(4), Some(teams), 6);
+        let before = state.side_scores();
+        state.charge(4, -2);
+        state.settle_points(&before, &mut Vec::new());
+        assert!(state.is_over());
+        let mut events = Vec::new();
+        state.end_game(&mut events);
+        assert!(matches!(
+            &events[..],
+            [Event::Ended { winners, winning_teams }] if *winners == [4] && *winning_teams == [1]
+        ));
+    }
+
+    #[test]
+    fn deck_turns_go_to_whoever_holds_cards() {
+        let deck = |size| GameConfig {
+            deck: Some(DeckOptions { size, hand_size: 3 }),
+            ..GameConfig::default()
+        };
+        let (mut state, events) = GameState::new(deck(3), names(3), None, 4);
+        // The first player dealt takes the whole deck, the others are passed over.
+        assert!(matches!(
+            events.last(),
+            Some(Event::TurnStarted { id: 1, cards, .. }) if cards.len() == 3
+        ));
+
+        let events = state.leave(1);
+        let next = state.playing();
+        assert_ne!(next, 1);
+        assert!(matches!(
+            &events[..],
+            [Event::Hand { id, .. }, Event::TurnStarted { cards, .. }]
+                if *id == next && cards.len() == 3
+        ));
+
+        let (state, events) = GameState::new(deck(0), names(3), None, 4);
+        assert!(state.is_over());
+        assert!(matches!(events.last(), Some(Event::Ended { .. })));
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+
+use rand::{seq::SliceRandom, Rng};
+use serde::Deserialize;
+
+/// Room settings for playing in teams.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TeamOptions {
+    /// Number of teams players are split into.
+    pub count: usize,
+    pub points: TeamPoints,
+}
+
+impl Default for TeamOptions {
+    fn default() -> Self {
+        Self {
+            count: 2,
+            points: TeamPoints::Pooled,
+        }
+    }
+}
+
+/// How the points of teammates relate.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TeamPoints {
+    /// Every gain and loss applies to the whole team, so teammates always share one score
+    /// and drop out together.
+    #[default]
+    Pooled,
+    /// Players keep their own points; the team score is their sum and the team is out once
+    /// every member is.
+    Summed,
+}
+
+/// Which team every player in the room is on, keyed by player id.
+#[derive(Debug, Clone)]
+pub struct Teams {
+    count: usize,
+    team_of: BTreeMap<usize, usize>,
+}
+
+impl Teams {
+    pub fn new(count: usize) -> Self {
+        Self {
+            count,
+            team_of: BTreeMap::new(),
+        }
+    }
+
+    /// Puts a new player on the smallest team, the first one on a tie.
+    pub fn join(&mut self, id: usize) -> usize {
+        let team = (0..self.count)
+            .min_by_key(|team| self.members(*team).count())
+            .unwrap_or_default();
+        self.team_of.insert(id, team);
+        team
+    }
+
+    /// Moves a player to `team`, returning `false` if there is no such team.
+    pub fn pick(&mut self, id: usize, team: usize) -> bool {
+        if team >= self.count {
+            return false;
+        }
+        self.team_of.insert(id, team);
+        true
+    }
+
+    pub fn leave(&mut self, id: &usize) {
+        self.team_of.remove(id);
+    }
+
+    pub fn team(&self, id: &usize) -> Option<usize> {
+        self.team_of.get(id).copied()
+    }
+
+    pub fn members(&self, team: usize) -> impl Iterator<Item = usize> + '_ {
+        self.team_of
+            .iter()
+            .filter(move |(_, of)| **of == team)
+            .map(|(id, _)| *id)
+    }
+
+    /// Number of teams with at least one player.
+    pub fn non_empty(&self) -> usize {
+        (0..self.count)
+            .filter(|team| self.members(*team).next().is_some())
+            .count()
+    }
+
+    /// Seats players at random but so that turns alternate between teams: the first player
+    /// of every team, then the second one, and so on. Larger teams go first, so that a team
+    /// only sits twice in a row once the others ran out of players.
+    pub fn turn_order(&self, rng: &mut impl Rng) -> Vec<usize> {
+        let mut teams = (0..self.count)
+            .map(|team| {
+                let mut members = self.members(team).collect::<Vec<_>>();
+                members.shuffle(rng);
+                members
+            })
+            .collect::<Vec<_>>();
+        teams.shuffle(rng);
+        teams.sort_by_key(|members| std::cmp::Reverse(members.len()));
+        let rounds = teams.iter().map(Vec::len).max().unwrap_or_default();
+        (0..rounds)
+            .flat_map(|round| {
+                teams
+                    .iter()
+                    .filter_map(move |team| team.get(round).copied())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn players_are_balanced_and_seated_alternately() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut teams = Teams::new(2);
+        let joined = (1..=5).map(|id| teams.join(id)).collect::<Vec<_>>();
+        assert_eq!(joined, [0, 1, 0, 1, 0]);
+        let assert_alternates = |teams: &Teams, rng: &mut StdRng, players: &[usize]| {
+            for _ in 0..50 {
+                let order = teams.turn_order(rng);
+                let mut seated = order.clone();
+                seated.sort_unstable();
+                assert_eq!(seated, players);
+                for pair in order.windows(2) {
+                    assert_ne!(teams.team(&pair[0]), teams.team(&pair[1]), "{order:?}");
+                }
+            }
+        };
+        assert_alternates(&teams, &mut rng, &[1, 2, 3, 4, 5]);
+
+        assert!(teams.pick(5, 1));
+        assert!(!teams.pick(5, 2));
+        teams.leave(&2);
+        assert_alternates(&teams, &mut rng, &[1, 3, 4, 5]);
+
+        assert!(teams.pick(4, 0));
+        assert!(teams.pick(5, 0));
+        assert_eq!(teams.non_empty(), 1);
+    }
+}
@@ -0,0 +1,163 @@
+use std::pin::Pin;
+
+use anyhow::{anyhow, Result};
+use futures::{stream, Sink, SinkExt, Stream, StreamExt};
+use serde_json::json;
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+use tokio_util::codec::{FramedRead, LinesCodec};
+use warp::filters::ws::{self, WebSocket};
+
+use crate::protocol::Frame;
+
+/// Longest line a TCP client may send; the connection is closed on a longer one.
+pub const MAX_LINE_LEN: usize = 64 * 1024;
+
+/// What goes over a connection, on top of the protocol frames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    Frame(Frame),
+    /// The peer went away, or the server is hanging up with a close code and reason.
+    Close(Option<(u16, String)>),
+}
+
+pub type PacketSink = Pin<Box<dyn Sink<Packet, Error = anyhow::Error> + Send>>;
+pub type PacketStream = Pin<Box<dyn Stream<Item = Result<Packet>> + Send>>;
+
+/// A client connection, independent of the transport it arrived on.
+pub struct Connection {
+    pub sink: PacketSink,
+    pub stream: PacketStream,
+    /// Whether binary frames can be sent, i.e. whether MessagePack can be negotiated.
+    pub binary: bool,
+}
+
+impl Connection {
+    pub fn websocket(ws: WebSocket) -> Self {
+        let (ws_sender, ws_receiver) = ws.split();
+        let sink = ws_sender
+            .sink_map_err(anyhow::Error::from)
+            .with(|packet| async move {
+                Ok::<_, anyhow::Error>(match packet {
+                    Packet::Frame(Frame::Text(text)) => ws::Message::text(text),
+                    Packet::Frame(Frame::Binary(bytes)) => ws::Message::binary(bytes),
+                    Packet::Close(Some((code, reason))) => ws::Message::close_with(code, reason),
+                    Packet::Close(None) => ws::Message::close(),
+                })
+            });
+        let stream = ws_receiver
+            .filter_map(|msg| async move {
+                match msg {
+                    Err(err) => Some(Err(err.into())),
+                    Ok(msg) if msg.is_close() => Some(Ok(Packet::Close(
+                        msg.close_frame()
+                            .map(|(code, reason)| (code, reason.to_string())),
+                    ))),
+                    Ok(msg) if msg.is_text() => msg
+                        .to_str()
+                        .ok()
+                        .map(|text| Ok(Packet::Frame(Frame::Text(text.to_string())))),
+                    Ok(msg) if msg.is_binary() => {
+                        Some(Ok(Packet::Frame(Frame::Binary(msg.into_bytes()))))
+                    }
+                    Ok(_) => None,
+                }
+            })
+            .chain(stream::once(async { Ok(Packet::Close(None)) }));
+        Self {
+            sink: Box::pin(sink),
+            stream: Box::pin(stream),
+            binary: true,
+        }
+    }
+
+    /// Newline-delimited JSON over a raw TCP socket.
+    ///
+    /// Every line is one JSON text frame in either direction. When the server hangs up it
+    /// writes a last `{"type":"close","code":...,"reason":...}` line before shutting down.
+    /// A line longer than [`MAX_LINE_LEN`] ends the stream with an error.
+    pub fn tcp(socket: TcpStream) -> Self {
+        let (read_half, write_half) = socket.into_split();
+        let lines = FramedRead::new(read_half, LinesCodec::new_with_max_length(MAX_LINE_LEN));
+        let stream = stream::unfold(Some(lines), |lines| async move {
+            let mut lines = lines?;
+            match lines.next().await {
+                Some(Ok(line)) => Some((Ok(Packet::Frame(Frame::Text(line))), Some(lines))),
+                None => Some((Ok(Packet::Close(None)), None)),
+                Some(Err(err)) => Some((Err(err.into()), None)),
+            }
+        })
+        .filter(|packet| {
+            let blank =
+                matches!(packet, Ok(Packet::Frame(Frame::Text(line))) if line.trim().is_empty());
+            async move { !blank }
+        });
+        let sink = futures::sink::unfold(write_half, |mut write_half, packet| async move {
+            match packet {
+                Packet::Frame(Frame::Text(text)) => {
+                    write_half.write_all(text.as_bytes()).await?;
+                    write_half.write_all(b"\n").await?;
+                }
+                Packet::Frame(Frame::Binary(_)) => {
+                    return Err(anyhow!("Binary frames are not supported over TCP"));
+                }
+                Packet::Close(close) => {
+                    if let Some((code, reason)) = close {
+                        let line = json!({ "type": "close", "code": code, "reason": reason });
+                        write_half.write_all(format!("{line}\n").as_bytes()).await?;
+                    }
+                    write_half.shutdown().await?;
+                }
+            }
+            Ok::<_, anyhow::Error>(write_half)
+        });
+        Self {
+            sink: Box::pin(sink),
+            stream: Box::pin(stream),
+            binary: false,
+        }
+    }
+}
+
+/// Sends the close packet and closes the sink.
+pub async fn close(sink: &mut PacketSink, code: u16, reason: impl Into<String>) -> Result<()> {
+    sink.send(Packet::Close(Some((code, reason.into()))))
+        .await?;
+    sink.close().await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn oversize_lines_close_tcp_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut connection = Connection::tcp(socket);
+
+        client.write_all(b"{\"type\":\"pass\"}\n").await.unwrap();
+        client
+            .write_all(&vec![b'x'; MAX_LINE_LEN + 1])
+            .await
+            .unwrap();
+        assert_eq!(
+            connection.stream.next().await.unwrap().unwrap(),
+            Packet::Frame(Frame::Text(r#"{"type":"pass"}"#.into()))
+        );
+        assert!(connection.stream.next().await.unwrap().is_err());
+        assert!(connection.stream.next().await.is_none());
+
+        drop(connection);
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
+}
@@ -0,0 +1,598 @@
+//! End-to-end games over WebSockets, against the warp filters served in-process, and over
+//! line-delimited JSON on a local TCP listener.
+
+use std::{sync::Arc, time::Duration};
+
+use chatroom_rust::{
+    card_set::CardSets,
+    game::GameOptions,
+    player::{ErrorCode, PlayerAction, PlayerMessage},
+    protocol::PROTOCOL_VERSION,
+    routes,
+    server::Server,
+    transport::MAX_LINE_LEN,
+};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{tcp::OwnedWriteHalf, TcpListener, TcpStream},
+    time::timeout,
+};
+use warp::{
+    http::StatusCode,
+    test::{self, WsClient},
+    Filter, Rejection, Reply,
+};
+
+fn routes() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone + 'static {
+    routes::routes(Server::new(CardSets::default()), "static", None).unwrap()
+}
+
+async fn create_game(
+    routes: &(impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone + 'static),
+    options: &str,
+) -> String {
+    let response = test::request()
+        .method("POST")
+        .path("/create-game")
+        .body(options)
+        .reply(routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = serde_json::from_slice(response.body()).unwrap();
+    body["game_code"].as_str().unwrap().to_string()
+}
+
+async fn game_exists(
+    routes: &(impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone + 'static),
+    game_code: &str,
+) -> bool {
+    let response = test::request()
+        .path(&format!("/game-exist/{game_code}"))
+        .reply(routes)
+        .await;
+    let body: Value = serde_json::from_slice(response.body()).unwrap();
+    body["game_exist"].as_bool().unwrap()
+}
+
+/// A player connected over a WebSocket, keeping every message it got.
+struct Client {
+    name: Arc<str>,
+    ws: WsClient,
+    seen: Vec<PlayerMessage>,
+}
+
+impl Client {
+    /// Connects, says hello and joins the game as `name`.
+    async fn join(
+        routes: &(impl Filter<Extract = (impl Reply,), Error = Rejection>
+              + Clone
+              + Send
+              + Sync
+              + 'static),
+        game_code: &str,
+        name: &str,
+    ) -> Self {
+        let ws = test::ws()
+            .path(&format!("/game/{game_code}"))
+            .handshake(routes.clone())
+            .await
+            .expect("Should upgrade");
+        let mut client = Self {
+            name: name.into(),
+            ws,
+            seen: Vec::new(),
+        };
+        client
+            .send(&PlayerAction::Hello {
+                protocol_version: PROTOCOL_VERSION,
+                client_name: "game-flow-test".into(),
+                capabilities: Vec::new(),
+            })
+            .await;
+        client
+            .wait_for(|msg| matches!(msg, PlayerMessage::Welcome { .. }))
+            .await;
+        client
+            .send(&PlayerAction::Join {
+                name: client.name.clone(),
+            })
+            .await;
+        client
+    }
+
+    async fn send(&mut self, action: &PlayerAction) {
+        self.ws
+            .send_text(serde_json::to_string(action).unwrap())
+            .await;
+    }
+
+    /// The next message, `None` once the server closed the connection.
+    async fn next(&mut self) -> Option<PlayerMessage> {
+        loop {
+            let frame = timeout(Duration::from_secs(5), self.ws.recv())
+                .await
+                .expect("Should get a message in time")
+                .ok()?;
+            if let Ok(text) = frame.to_str() {
+                self.seen.push(serde_json::from_str(text).unwrap());
+                return serde_json::from_str(text).ok();
+            }
+            if frame.is_close() {
+                return None;
+            }
+        }
+    }
+
+    async fn wait_for(&mut self, expected: impl Fn(&PlayerMessage) -> bool) -> PlayerMessage {
+        loop {
+            let msg = self
+                .next()
+                .await
+                .unwrap_or_else(|| panic!("{} was disconnected", self.name));
+            if expected(&msg) {
+                return msg;
+            }
+        }
+    }
+
+    /// Waits for the next turn, returning who plays, or `None` once the game is over.
+    async fn next_turn(&mut self) -> Option<Arc<str>> {
+        let msg = self
+            .wait_for(|msg| {
+                matches!(
+                    msg,
+                    PlayerMessage::RoundStart { .. } | PlayerMessage::GameEnd { .. }
+                )
+            })
+            .await;
+        match msg {
+            PlayerMessage::RoundStart { player_name, .. } => Some(player_name),
+            _ => None,
+        }
+    }
+
+    fn count(&self, kind: impl Fn(&PlayerMessage) -> bool) -> usize {
+        self.seen.iter().filter(|msg| kind(msg)).count()
+    }
+}
+
+/// A player connected over TCP, one JSON message per line.
+struct TcpClient {
+    lines: tokio::io::Lines<BufReader<tokio::net::tcp::OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl TcpClient {
+    /// Connects, says hello and joins the game as `name`.
+    async fn join(listener: &str, game_code: &str, name: &str) -> Self {
+        let (reader, writer) = TcpStream::connect(listener).await.unwrap().into_split();
+        let mut client = Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        };
+        client.write(game_code.as_bytes()).await;
+        client
+            .send(&PlayerAction::Hello {
+                protocol_version: PROTOCOL_VERSION,
+                client_name: "game-flow-test".into(),
+                capabilities: Vec::new(),
+            })
+            .await;
+        client
+            .wait_for(|msg| matches!(msg, PlayerMessage::Welcome { .. }))
+            .await;
+        client.send(&PlayerAction::Join { name: name.into() }).await;
+        client
+            .wait_for(|msg| matches!(msg, PlayerMessage::Joined { .. }))
+            .await;
+        client
+    }
+
+    async fn write(&mut self, line: &[u8]) {
+        self.writer.write_all(line).await.unwrap();
+        self.writer.write_all(b"\n").await.unwrap();
+    }
+
+    async fn send(&mut self, action: &PlayerAction) {
+        self.write(serde_json::to_string(action).unwrap().as_bytes())
+            .await;
+    }
+
+    async fn wait_for(&mut self, expected: impl Fn(&PlayerMessage) -> bool) -> PlayerMessage {
+        loop {
+            let line = timeout(Duration::from_secs(2), self.lines.next_line())
+                .await
+                .expect("Should get a message in time")
+                .unwrap()
+                .expect("Should still be connected");
+            if let Ok(msg) = serde_json::from_str(&line) {
+                if expected(&msg) {
+                    return msg;
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn http_endpoints() {
+    let routes = routes();
+    let game_code = create_game(&routes, "").await;
+    assert!(game_exists(&routes, &game_code).await);
+    assert!(!game_exists(&routes, "nope").await);
+
+    for options in [
+        r#"{"plays_per_turn":0}"#,
+        r#"{"discard_cost":-1}"#,
+        r#"{"point_target":10}"#,
+        r#"{"sudden_death":{"min_len":11}}"#,
+        r#"{"bogus":1}"#,
+        "not json",
+    ] {
+        let response = test::request()
+            .method("POST")
+            .path("/create-game")
+            .body(options)
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{options}");
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert!(body["error"].is_string());
+    }
+
+    let response = test::request()
+        .method("POST")
+        .path("/create-game")
+        .body(vec![b' '; 1024 * 1024])
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let response = test::request()
+        .method("POST")
+        .path("/create-game")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let server = || Server::new(CardSets::default());
+    assert!(routes::routes(
+        server(),
+        "static",
+        Some("https://a.example, http://b.example:8080")
+    )
+    .is_ok());
+    for origins in [
+        "a.example",
+        "https://a.example, not an origin",
+        "https://a.example/path",
+    ] {
+        let err = routes::routes(server(), "static", Some(origins))
+            .err()
+            .unwrap();
+        let invalid = origins.rsplit(", ").next().unwrap();
+        assert!(err.to_string().contains(invalid), "{err}");
+    }
+
+    // Other sites only get CORS headers once they are allowed.
+    for (allowed_origins, allowed) in [(None, false), (Some("https://a.example"), true)] {
+        let routes = routes::routes(server(), "static", allowed_origins).unwrap();
+        let response = test::request()
+            .path("/card-sets")
+            .header("origin", "https://a.example")
+            .reply(&routes)
+            .await;
+        assert_eq!(
+            response
+                .headers()
+                .get("access-control-allow-origin")
+                .is_some(),
+            allowed
+        );
+    }
+
+    let response = test::request().path("/card-sets").reply(&routes).await;
+    let body: Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["card_sets"], serde_json::json!(["default"]));
+
+    let response = test::request()
+        .path("/protocol/schema")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let schema: Value = serde_json::from_slice(response.body()).unwrap();
+    assert!(schema.is_object());
+}
+
+#[tokio::test]
+async fn two_players_play_a_full_game() {
+    let routes = routes();
+    let game_code = create_game(&routes, r#"{"round_limit":15}"#).await;
+    let mut alice = Client::join(&routes, &game_code, "alice").await;
+    assert_eq!(
+        alice
+            .wait_for(|msg| matches!(msg, PlayerMessage::Joined { .. }))
+            .await,
+        PlayerMessage::Joined {
+            players_name: Vec::new()
+        }
+    );
+    alice.wait_for(|msg| *msg == PlayerMessage::HostStart).await;
+    let mut bob = Client::join(&routes, &game_code, "bob").await;
+    assert_eq!(
+        bob.wait_for(|msg| matches!(msg, PlayerMessage::Joined { .. }))
+            .await,
+        PlayerMessage::Joined {
+            players_name: vec!["alice".into()]
+        }
+    );
+    alice
+        .wait_for(|msg| matches!(msg, PlayerMessage::NewPlayer { name } if &**name == "bob"))
+        .await;
+
+    alice.send(&PlayerAction::Start).await;
+    for client in [&mut alice, &mut bob] {
+        let PlayerMessage::Start { point, seating } = client
+            .wait_for(|msg| matches!(msg, PlayerMessage::Start { .. }))
+            .await
+        else {
+            unreachable!();
+        };
+        assert_eq!(point, 10);
+        let mut seated = seating.iter().map(|name| &**name).collect::<Vec<_>>();
+        seated.sort_unstable();
+        assert_eq!(seated, ["alice", "bob"]);
+    }
+
+    let mut turns = 0;
+    let mut plays = [0, 0];
+    loop {
+        let playing = alice.next_turn().await;
+        assert_eq!(bob.next_turn().await, playing);
+        let Some(playing) = playing else {
+            break;
+        };
+        turns += 1;
+        assert!(turns <= 30, "the round limit should end the game");
+        let (player, other, played) = if *playing == *alice.name {
+            (&mut alice, &mut bob, &mut plays[0])
+        } else {
+            (&mut bob, &mut alice, &mut plays[1])
+        };
+        if turns == 1 {
+            other.send(&PlayerAction::Pass).await;
+            let error = other
+                .wait_for(|msg| matches!(msg, PlayerMessage::Error { .. }))
+                .await;
+            assert!(matches!(
+                error,
+                PlayerMessage::Error {
+                    code: ErrorCode::NotYourTurn,
+                    ..
+                }
+            ));
+        }
+        let PlayerMessage::NewRound { cards, .. } = player
+            .wait_for(|msg| matches!(msg, PlayerMessage::NewRound { .. }))
+            .await
+        else {
+            unreachable!();
+        };
+        assert_eq!(cards.len(), 3);
+        player
+            .send(&PlayerAction::UseCard {
+                card_index: 0,
+                target: None,
+            })
+            .await;
+        *played += 1;
+    }
+
+    let end = |client: &Client| {
+        client
+            .seen
+            .iter()
+            .find_map(|msg| match msg {
+                PlayerMessage::GameEnd { winner_names, .. } => Some(winner_names.clone()),
+                _ => None,
+            })
+            .unwrap()
+    };
+    let winners = end(&alice);
+    assert_eq!(winners, end(&bob));
+    assert!(!winners.is_empty());
+    for client in [&mut alice, &mut bob] {
+        let won = client.count(|msg| *msg == PlayerMessage::Win) == 1;
+        assert_eq!(won, winners.contains(&client.name));
+        client
+            .wait_for(|msg| *msg == PlayerMessage::GameEnded)
+            .await;
+        assert_eq!(client.next().await, None);
+    }
+    let other_plays =
+        |client: &Client| client.count(|msg| matches!(msg, PlayerMessage::OtherUseCard { .. }));
+    assert_eq!(other_plays(&alice), plays[1]);
+    assert_eq!(other_plays(&bob), plays[0]);
+
+    for _ in 0..50 {
+        if !game_exists(&routes, &game_code).await {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("The game should be removed once it ended");
+}
+
+#[tokio::test]
+async fn late_joiners_are_turned_away_and_the_last_player_wins() {
+    let routes = routes();
+    let game_code = create_game(&routes, "").await;
+    let mut clients = Vec::new();
+    for name in ["ann", "ben", "cid"] {
+        clients.push(Client::join(&routes, &game_code, name).await);
+    }
+    clients[0]
+        .wait_for(|msg| matches!(msg, PlayerMessage::NewPlayer { name } if &**name == "cid"))
+        .await;
+    clients[0].send(&PlayerAction::Start).await;
+
+    let mut late = Client::join(&routes, &game_code, "dan").await;
+    late.wait_for(|msg| *msg == PlayerMessage::GameStarted)
+        .await;
+    assert_eq!(late.next().await, None);
+
+    // The playing player leaving passes the turn on.
+    let mut playing = None;
+    for client in &mut clients {
+        playing = client.next_turn().await;
+    }
+    let quitter = clients
+        .iter()
+        .position(|client| Some(&client.name) == playing.as_ref())
+        .unwrap();
+    let mut quitter = clients.remove(quitter);
+    quitter.send(&PlayerAction::Quit).await;
+    let mut next = None;
+    for client in &mut clients {
+        next = client.next_turn().await;
+        assert!(next.is_some());
+        assert_ne!(next, playing);
+    }
+
+    // Then the game ends as soon as one player is left.
+    let leaving = clients
+        .iter()
+        .position(|client| Some(&client.name) != next.as_ref());
+    let mut leaving = clients.remove(leaving.unwrap_or(0));
+    leaving.send(&PlayerAction::Quit).await;
+    let mut winner = clients.remove(0);
+    assert_eq!(winner.next_turn().await, None);
+    assert!(winner.seen.contains(&PlayerMessage::Win));
+    assert!(winner.seen.contains(&PlayerMessage::GameEnd {
+        winner_name: Some(winner.name.clone()),
+        winner_names: vec![winner.name.clone()],
+        winning_teams: Vec::new(),
+    }));
+}
+
+#[tokio::test]
+async fn an_oversize_tcp_line_counts_as_quitting() {
+    let server = Server::new(CardSets::default());
+    let game_code = server.new_game(GameOptions::default()).await.unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(server.clone().accept_tcp_players(listener));
+
+    let mut ann = TcpClient::join(&addr, &game_code, "ann").await;
+    let mut ben = TcpClient::join(&addr, &game_code, "ben").await;
+    ann.wait_for(|msg| matches!(msg, PlayerMessage::NewPlayer { .. }))
+        .await;
+    ann.send(&PlayerAction::Start).await;
+    let mut playing = None;
+    for client in [&mut ann, &mut ben] {
+        let PlayerMessage::RoundStart { player_name, .. } = client
+            .wait_for(|msg| matches!(msg, PlayerMessage::RoundStart { .. }))
+            .await
+        else {
+            unreachable!();
+        };
+        playing = Some(player_name);
+    }
+
+    let (mut quitter, mut other, other_name) = match playing.as_deref() {
+        Some("ann") => (ann, ben, "ben"),
+        _ => (ben, ann, "ann"),
+    };
+    quitter.write(&vec![b'x'; MAX_LINE_LEN + 1]).await;
+    let end = other
+        .wait_for(|msg| matches!(msg, PlayerMessage::GameEnd { .. }))
+        .await;
+    assert!(matches!(
+        end,
+        PlayerMessage::GameEnd { winner_name: Some(name), .. } if &*name == other_name
+    ));
+    other.wait_for(|msg| *msg == PlayerMessage::GameEnded).await;
+    for _ in 0..50 {
+        if !server.is_game_exist(&game_code).await {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("The game should be removed once it ended");
+}
+
+#[tokio::test]
+async fn names_must_be_unique_and_not_empty() {
+    let routes = routes();
+    let game_code = create_game(&routes, "").await;
+    let mut ann = Client::join(&routes, &game_code, "ann").await;
+    ann.wait_for(|msg| *msg == PlayerMessage::HostStart).await;
+
+    let mut other = Client::join(&routes, &game_code, "ann").await;
+    for name in ["", " ", "ben"] {
+        let error = other
+            .wait_for(|msg| matches!(msg, PlayerMessage::Error { .. }))
+            .await;
+        assert!(matches!(
+            error,
+            PlayerMessage::Error {
+                code: ErrorCode::InvalidName,
+                ..
+            }
+        ));
+        other.send(&PlayerAction::Join { name: name.into() }).await;
+    }
+    assert_eq!(
+        other
+            .wait_for(|msg| matches!(msg, PlayerMessage::Joined { .. }))
+            .await,
+        PlayerMessage::Joined {
+            players_name: vec!["ann".into()]
+        }
+    );
+    assert_eq!(
+        ann.wait_for(|msg| matches!(msg, PlayerMessage::NewPlayer { .. }))
+            .await,
+        PlayerMessage::NewPlayer { name: "ben".into() }
+    );
+}
+
+#[tokio::test]
+async fn a_player_leaving_right_after_joining_does_not_keep_the_room() {
+    let server = Server::new(CardSets::default());
+    let game_code = server.new_game(GameOptions::default()).await.unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(server.clone().accept_tcp_players(listener));
+
+    let (reader, mut writer) = TcpStream::connect(&addr).await.unwrap().into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let hello = PlayerAction::Hello {
+        protocol_version: PROTOCOL_VERSION,
+        client_name: "game-flow-test".into(),
+        capabilities: Vec::new(),
+    };
+    let join = PlayerAction::Join { name: "ann".into() };
+    writer
+        .write_all(format!("{game_code}\n{}\n", serde_json::to_string(&hello).unwrap()).as_bytes())
+        .await
+        .unwrap();
+    lines
+        .next_line()
+        .await
+        .unwrap()
+        .expect("Should be welcomed");
+    writer
+        .write_all(format!("{}\n", serde_json::to_string(&join).unwrap()).as_bytes())
+        .await
+        .unwrap();
+    drop((lines, writer));
+
+    for _ in 0..50 {
+        if !server.is_game_exist(&game_code).await {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("The room should be removed once its only player left");
+}